@@ -0,0 +1,822 @@
+pub mod apu {
+
+    use std::collections::VecDeque;
+    use crate::header::header::Region;
+    use crate::savestate::savestate::stateful;
+
+    //CPU clock for NTSC, PAL and Dendy, the APU is driven by the CPU clock
+    const NTSC_CPU_FREQUENCY: f64 = 1789773.0;
+    const PAL_CPU_FREQUENCY: f64 = 1662607.0;
+    const DENDY_CPU_FREQUENCY: f64 = 1773448.0;
+    pub const SAMPLE_RATE: f64 = 44100.0;
+
+    //Samples kept when nobody drains them, one second's worth, the oldest are dropped first
+    const MAX_BUFFERED_SAMPLES: usize = SAMPLE_RATE as usize;
+
+    //Values loaded into the length counters, indexed by bits 3-7 of $4003/$4007/$400B/$400F
+    const LENGTH_TABLE: [u8; 32] = [
+        10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+        12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30
+    ];
+
+    const DUTY_TABLE: [[u8; 8]; 4] = [
+        [0, 1, 0, 0, 0, 0, 0, 0],
+        [0, 1, 1, 0, 0, 0, 0, 0],
+        [0, 1, 1, 1, 1, 0, 0, 0],
+        [1, 0, 0, 1, 1, 1, 1, 1]
+    ];
+
+    const TRIANGLE_TABLE: [u8; 32] = [
+        15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+        0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15
+    ];
+
+    //Noise timer periods in CPU cycles, NTSC then PAL
+    const NOISE_TABLE: [[u16; 16]; 2] = [
+        [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068],
+        [4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778]
+    ];
+
+    //DMC output rates in CPU cycles, NTSC then PAL
+    const DMC_TABLE: [[u16; 16]; 2] = [
+        [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54],
+        [398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50]
+    ];
+
+    //Frame counter steps 1-5 in CPU cycles after the sequencer was reset, NTSC then PAL
+    const FRAME_STEPS: [[u32; 5]; 2] = [
+        [7457, 14913, 22371, 29829, 37281],
+        [8313, 16627, 24939, 33253, 41565]
+    ];
+
+
+    #[derive(Default)]
+    struct LengthCounter {
+        enabled: bool,
+        halt: bool,
+        counter: u8,
+    }
+
+    stateful!(LengthCounter { enabled, halt, counter });
+
+    impl LengthCounter {
+        fn load(&mut self, index: u8) {
+            if self.enabled {
+                self.counter = LENGTH_TABLE[(index >> 3) as usize];
+            }
+        }
+
+        fn set_enabled(&mut self, enabled: bool) {
+            self.enabled = enabled;
+            if !enabled {
+                self.counter = 0;
+            }
+        }
+
+        //Half frame
+        fn clock(&mut self) {
+            if !self.halt && self.counter > 0 {
+                self.counter -= 1;
+            }
+        }
+
+        fn active(&self) -> bool {
+            self.counter > 0
+        }
+    }
+
+
+    //Volume envelope shared by the pulse and noise channels, the loop flag doubles as the length counter halt
+    #[derive(Default)]
+    struct Envelope {
+        start: bool,
+        looping: bool,
+        constant_volume: bool,
+        period: u8,
+        divider: u8,
+        decay: u8,
+    }
+
+    stateful!(Envelope { start, looping, constant_volume, period, divider, decay });
+
+    impl Envelope {
+        fn write(&mut self, value: u8) {
+            self.looping = value & 0x20 != 0;
+            self.constant_volume = value & 0x10 != 0;
+            self.period = value & 0x0F;
+        }
+
+        //Quarter frame
+        fn clock(&mut self) {
+            if self.start {
+                self.start = false;
+                self.decay = 15;
+                self.divider = self.period;
+            } else if self.divider == 0 {
+                self.divider = self.period;
+                if self.decay > 0 {
+                    self.decay -= 1;
+                } else if self.looping {
+                    self.decay = 15;
+                }
+            } else {
+                self.divider -= 1;
+            }
+        }
+
+        fn volume(&self) -> u8 {
+            if self.constant_volume {self.period} else {self.decay}
+        }
+    }
+
+
+    //Also used for the MMC5's pulse channels, which have no sweep unit
+    #[derive(Default)]
+    pub struct Pulse {
+        //Pulse 1 negates with one's complement, pulse 2 with two's complement
+        ones_complement: bool,
+        //Expansion audio pulse, never muted by the sweep unit or by low periods
+        expansion: bool,
+        duty: u8,
+        sequence_step: u8,
+        timer_period: u16,
+        timer: u16,
+        envelope: Envelope,
+        length: LengthCounter,
+
+        sweep_enabled: bool,
+        sweep_period: u8,
+        sweep_negate: bool,
+        sweep_shift: u8,
+        sweep_reload: bool,
+        sweep_divider: u8,
+    }
+
+    stateful!(Pulse {
+        duty, sequence_step, timer_period, timer, envelope, length, sweep_enabled, sweep_period, sweep_negate,
+        sweep_shift, sweep_reload, sweep_divider
+    });
+
+    impl Pulse {
+        pub fn expansion() -> Pulse {
+            Pulse { expansion: true, ..Default::default() }
+        }
+
+        pub fn register_write(&mut self, register: u16, value: u8) {
+            match register {
+                0 => {
+                    self.duty = value >> 6;
+                    self.envelope.write(value);
+                    self.length.halt = value & 0x20 != 0;
+                },
+                1 => {
+                    self.sweep_enabled = value & 0x80 != 0;
+                    self.sweep_period = (value >> 4) & 0x07;
+                    self.sweep_negate = value & 0x08 != 0;
+                    self.sweep_shift = value & 0x07;
+                    self.sweep_reload = true;
+                },
+                2 => self.timer_period = (self.timer_period & 0x0700) | value as u16,
+                _ => {
+                    self.timer_period = (self.timer_period & 0x00FF) | (value as u16 & 0x07) << 8;
+                    self.length.load(value);
+                    self.sequence_step = 0;
+                    self.envelope.start = true;
+                }
+            }
+        }
+
+        //Every APU cycle, 2 CPU cycles
+        pub fn clock_timer(&mut self) {
+            if self.timer == 0 {
+                self.timer = self.timer_period;
+                self.sequence_step = (self.sequence_step + 1) & 0x07;
+            } else {
+                self.timer -= 1;
+            }
+        }
+
+        //The sweep unit continuously computes its target period, even when disabled
+        fn sweep_target(&self) -> u16 {
+            let change = self.timer_period >> self.sweep_shift;
+            if self.sweep_negate {
+                self.timer_period.saturating_sub(change + if self.ones_complement {1} else {0})
+            } else {
+                self.timer_period + change
+            }
+        }
+
+        fn muted(&self) -> bool {
+            !self.expansion && (self.timer_period < 8 || self.sweep_target() > 0x07FF)
+        }
+
+        pub fn quarter_frame(&mut self) {
+            self.envelope.clock();
+        }
+
+        pub fn half_frame(&mut self) {
+            self.length.clock();
+        }
+
+        pub fn set_enabled(&mut self, enabled: bool) {
+            self.length.set_enabled(enabled);
+        }
+
+        pub fn active(&self) -> bool {
+            self.length.active()
+        }
+
+        //Half frame
+        fn clock_sweep(&mut self) {
+            if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+                self.timer_period = self.sweep_target();
+            }
+            if self.sweep_divider == 0 || self.sweep_reload {
+                self.sweep_divider = self.sweep_period;
+                self.sweep_reload = false;
+            } else {
+                self.sweep_divider -= 1;
+            }
+        }
+
+        pub fn output(&self) -> u8 {
+            if !self.length.active() || self.muted() || DUTY_TABLE[self.duty as usize][self.sequence_step as usize] == 0 {
+                0
+            } else {
+                self.envelope.volume()
+            }
+        }
+    }
+
+
+    #[derive(Default)]
+    struct Triangle {
+        sequence_step: u8,
+        timer_period: u16,
+        timer: u16,
+        length: LengthCounter,
+
+        //The control flag doubles as the length counter halt
+        control: bool,
+        linear_reload_value: u8,
+        linear_counter: u8,
+        linear_reload: bool,
+    }
+
+    stateful!(Triangle {
+        sequence_step, timer_period, timer, length, control, linear_reload_value, linear_counter, linear_reload
+    });
+
+    impl Triangle {
+        fn register_write(&mut self, register: u16, value: u8) {
+            match register {
+                0 => {
+                    self.control = value & 0x80 != 0;
+                    self.length.halt = self.control;
+                    self.linear_reload_value = value & 0x7F;
+                },
+                1 => (),
+                2 => self.timer_period = (self.timer_period & 0x0700) | value as u16,
+                _ => {
+                    self.timer_period = (self.timer_period & 0x00FF) | (value as u16 & 0x07) << 8;
+                    self.length.load(value);
+                    self.linear_reload = true;
+                }
+            }
+        }
+
+        //Every CPU cycle, the sequencer only moves while both counters are non-zero
+        fn clock_timer(&mut self) {
+            if self.timer == 0 {
+                self.timer = self.timer_period;
+                if self.length.active() && self.linear_counter > 0 {
+                    self.sequence_step = (self.sequence_step + 1) & 0x1F;
+                }
+            } else {
+                self.timer -= 1;
+            }
+        }
+
+        //Quarter frame
+        fn clock_linear(&mut self) {
+            if self.linear_reload {
+                self.linear_counter = self.linear_reload_value;
+            } else if self.linear_counter > 0 {
+                self.linear_counter -= 1;
+            }
+            if !self.control {
+                self.linear_reload = false;
+            }
+        }
+
+        //Silencing the triangle would pop, it holds its current step instead
+        fn output(&self) -> u8 {
+            TRIANGLE_TABLE[self.sequence_step as usize]
+        }
+    }
+
+
+    struct Noise {
+        pal: bool,
+        //Mode 1 feeds back from bit 6 instead of bit 1, giving a short, metallic sounding sequence
+        mode: bool,
+        shift_register: u16,
+        timer_period: u16,
+        timer: u16,
+        envelope: Envelope,
+        length: LengthCounter,
+    }
+
+    stateful!(Noise { mode, shift_register, timer_period, timer, envelope, length });
+
+    impl Noise {
+        fn new() -> Noise {
+            Noise {
+                pal: false,
+                mode: false,
+                shift_register: 1,
+                timer_period: NOISE_TABLE[0][0],
+                timer: 0,
+                envelope: Envelope::default(),
+                length: LengthCounter::default(),
+            }
+        }
+
+        fn register_write(&mut self, register: u16, value: u8) {
+            match register {
+                0 => {
+                    self.envelope.write(value);
+                    self.length.halt = value & 0x20 != 0;
+                },
+                1 => (),
+                2 => {
+                    self.mode = value & 0x80 != 0;
+                    self.timer_period = NOISE_TABLE[self.pal as usize][(value & 0x0F) as usize];
+                },
+                _ => {
+                    self.length.load(value);
+                    self.envelope.start = true;
+                }
+            }
+        }
+
+        //Every CPU cycle, the period table is in CPU cycles
+        fn clock_timer(&mut self) {
+            if self.timer == 0 {
+                self.timer = self.timer_period - 1;
+                let tap = if self.mode {6} else {1};
+                let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 0x01;
+                self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+            } else {
+                self.timer -= 1;
+            }
+        }
+
+        fn output(&self) -> u8 {
+            if !self.length.active() || self.shift_register & 0x01 != 0 {
+                0
+            } else {
+                self.envelope.volume()
+            }
+        }
+    }
+
+
+    /*
+        Delta modulation channel, plays 1-bit delta encoded samples from $C000-$FFFF.
+        The memory reader can't access the bus itself, it raises a DMA request that the CPU services
+        by halting and reading the byte for it.
+    */
+    struct Dmc {
+        pal: bool,
+        irq_enabled: bool,
+        irq: bool,
+        looping: bool,
+        timer_period: u16,
+        timer: u16,
+        output_level: u8,
+
+        //Memory reader
+        sample_address: u16,
+        sample_length: u16,
+        current_address: u16,
+        bytes_remaining: u16,
+        sample_buffer: Option<u8>,
+
+        //Output unit
+        shift_register: u8,
+        bits_remaining: u8,
+        silence: bool,
+    }
+
+    stateful!(Dmc {
+        irq_enabled, irq, looping, timer_period, timer, output_level, sample_address, sample_length, current_address,
+        bytes_remaining, sample_buffer, shift_register, bits_remaining, silence
+    });
+
+    impl Dmc {
+        fn new() -> Dmc {
+            Dmc {
+                pal: false,
+                irq_enabled: false,
+                irq: false,
+                looping: false,
+                timer_period: DMC_TABLE[0][0],
+                timer: 0,
+                output_level: 0,
+                sample_address: 0xC000,
+                sample_length: 1,
+                current_address: 0xC000,
+                bytes_remaining: 0,
+                sample_buffer: None,
+                shift_register: 0,
+                bits_remaining: 8,
+                silence: true,
+            }
+        }
+
+        fn register_write(&mut self, register: u16, value: u8) {
+            match register {
+                0 => {
+                    self.irq_enabled = value & 0x80 != 0;
+                    self.looping = value & 0x40 != 0;
+                    self.timer_period = DMC_TABLE[self.pal as usize][(value & 0x0F) as usize];
+                    if !self.irq_enabled {
+                        self.irq = false;
+                    }
+                },
+                1 => self.output_level = value & 0x7F,
+                2 => self.sample_address = 0xC000 | (value as u16) << 6,
+                _ => self.sample_length = ((value as u16) << 4) + 1
+            }
+        }
+
+        fn set_enabled(&mut self, enabled: bool) {
+            self.irq = false;
+            if !enabled {
+                self.bytes_remaining = 0;
+            } else if self.bytes_remaining == 0 {
+                self.restart();
+            }
+        }
+
+        fn restart(&mut self) {
+            self.current_address = self.sample_address;
+            self.bytes_remaining = self.sample_length;
+        }
+
+        //Address the memory reader is waiting on, if any
+        fn dma_address(&self) -> Option<u16> {
+            if self.sample_buffer.is_none() && self.bytes_remaining > 0 {Some(self.current_address)} else {None}
+        }
+
+        fn dma_complete(&mut self, value: u8) {
+            self.sample_buffer = Some(value);
+            //The address wraps around to $8000, not $C000
+            self.current_address = if self.current_address == 0xFFFF {0x8000} else {self.current_address + 1};
+            self.bytes_remaining -= 1;
+            if self.bytes_remaining == 0 {
+                if self.looping {
+                    self.restart();
+                } else if self.irq_enabled {
+                    self.irq = true;
+                }
+            }
+        }
+
+        //Every CPU cycle, the rate table is in CPU cycles
+        fn clock_timer(&mut self) {
+            if self.timer > 0 {
+                self.timer -= 1;
+                return;
+            }
+            self.timer = self.timer_period - 1;
+
+            //Each bit moves the output level up or down by 2, staying within 0-127
+            if !self.silence {
+                if self.shift_register & 0x01 != 0 {
+                    if self.output_level <= 125 { self.output_level += 2; }
+                } else if self.output_level >= 2 {
+                    self.output_level -= 2;
+                }
+            }
+            self.shift_register >>= 1;
+
+            self.bits_remaining -= 1;
+            if self.bits_remaining == 0 {
+                self.bits_remaining = 8;
+                match self.sample_buffer.take() {
+                    Some(value) => {
+                        self.silence = false;
+                        self.shift_register = value;
+                    },
+                    None => self.silence = true
+                }
+            }
+        }
+    }
+
+
+    pub struct Ricoh2a03 {
+        pulse_1: Pulse,
+        pulse_2: Pulse,
+        triangle: Triangle,
+        noise: Noise,
+        dmc: Dmc,
+
+        //Frame counter, $4017
+        frame_steps: [u32; 5],
+        five_step_mode: bool,
+        irq_inhibit: bool,
+        frame_irq: bool,
+        frame_cycle: u32,
+        //A write to $4017 resets the sequencer 3 or 4 CPU cycles later
+        frame_reset_delay: u8,
+        cycle: u64,
+
+        //Cartridge audio, already scaled to the mixer's output
+        expansion_output: f32,
+
+        //Output downsampled to SAMPLE_RATE
+        cpu_frequency: f64,
+        sample_clock: f64,
+        samples: VecDeque<f32>,
+    }
+
+    stateful!(Ricoh2a03 {
+        pulse_1, pulse_2, triangle, noise, dmc, five_step_mode, irq_inhibit, frame_irq, frame_cycle, frame_reset_delay,
+        cycle, expansion_output, sample_clock
+    });
+
+
+    impl Default for Ricoh2a03 {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+
+    impl Ricoh2a03 {
+
+        pub fn new() -> Ricoh2a03 {
+            Ricoh2a03 {
+                pulse_1: Pulse { ones_complement: true, ..Default::default() },
+                pulse_2: Pulse::default(),
+                triangle: Triangle::default(),
+                noise: Noise::new(),
+                dmc: Dmc::new(),
+
+                frame_steps: FRAME_STEPS[0],
+                five_step_mode: false,
+                irq_inhibit: false,
+                frame_irq: false,
+                frame_cycle: 0,
+                frame_reset_delay: 0,
+                cycle: 0,
+
+                expansion_output: 0.0,
+
+                cpu_frequency: NTSC_CPU_FREQUENCY,
+                sample_clock: 0.0,
+                samples: VecDeque::new(),
+            }
+        }
+
+        //PAL uses its own noise, DMC and frame counter periods. The Dendy keeps NTSC's at a slower CPU clock
+        pub fn set_region(&mut self, region: Region) {
+            let pal = region == Region::Pal;
+            self.noise.pal = pal;
+            self.dmc.pal = pal;
+            self.frame_steps = FRAME_STEPS[pal as usize];
+            self.cpu_frequency = match region {
+                Region::Pal => PAL_CPU_FREQUENCY,
+                Region::Dendy => DENDY_CPU_FREQUENCY,
+                _ => NTSC_CPU_FREQUENCY
+            };
+        }
+
+
+        //Reset silences every channel as if $4015 was written with 0, the frame counter mode is kept
+        pub fn reset(&mut self) {
+            self.register_write(0x4015, 0);
+            self.frame_irq = false;
+            self.frame_cycle = 0;
+        }
+
+
+        //$4015 read: length counter status of each channel, whether the DMC is still playing and both interrupt flags.
+        //Only the frame interrupt flag is cleared by the read
+        pub fn read_status(&mut self) -> u8 {
+            let mut status = 0;
+            if self.pulse_1.length.active() { status |= 0x01; }
+            if self.pulse_2.length.active() { status |= 0x02; }
+            if self.triangle.length.active() { status |= 0x04; }
+            if self.noise.length.active() { status |= 0x08; }
+            if self.dmc.bytes_remaining > 0 { status |= 0x10; }
+            if self.frame_irq { status |= 0x40; }
+            if self.dmc.irq { status |= 0x80; }
+            self.frame_irq = false;
+            status
+        }
+
+
+        pub fn register_write(&mut self, addr: u16, value: u8) {
+            match addr {
+                0x4000..=0x4003 => self.pulse_1.register_write(addr & 0x03, value),
+                0x4004..=0x4007 => self.pulse_2.register_write(addr & 0x03, value),
+                0x4008..=0x400B => self.triangle.register_write(addr & 0x03, value),
+                0x400C..=0x400F => self.noise.register_write(addr & 0x03, value),
+                0x4010..=0x4013 => self.dmc.register_write(addr & 0x03, value),
+                0x4015 => {
+                    self.pulse_1.length.set_enabled(value & 0x01 != 0);
+                    self.pulse_2.length.set_enabled(value & 0x02 != 0);
+                    self.triangle.length.set_enabled(value & 0x04 != 0);
+                    self.noise.length.set_enabled(value & 0x08 != 0);
+                    self.dmc.set_enabled(value & 0x10 != 0);
+                },
+                0x4017 => {
+                    self.five_step_mode = value & 0x80 != 0;
+                    self.irq_inhibit = value & 0x40 != 0;
+                    if self.irq_inhibit {
+                        self.frame_irq = false;
+                    }
+                    self.frame_reset_delay = if self.cycle.is_multiple_of(2) {3} else {4};
+                },
+                _ => ()
+            }
+        }
+
+
+        //State of the frame counter's contribution to the IRQ line
+        pub fn frame_irq(&self) -> bool {
+            self.frame_irq
+        }
+
+
+        pub fn dmc_irq(&self) -> bool {
+            self.dmc.irq
+        }
+
+
+        //Address the DMC needs read by a DMA, the CPU services it by halting on its next read cycle
+        pub fn dmc_dma_address(&self) -> Option<u16> {
+            self.dmc.dma_address()
+        }
+
+
+        pub fn dmc_dma_complete(&mut self, value: u8) {
+            self.dmc.dma_complete(value);
+        }
+
+
+        pub fn set_expansion_output(&mut self, value: f32) {
+            self.expansion_output = value;
+        }
+
+
+        //Samples generated since the last call, mono at SAMPLE_RATE in the range 0.0-1.0
+        pub fn take_samples(&mut self) -> Vec<f32> {
+            self.samples.drain(..).collect()
+        }
+
+
+        //Run the APU for one CPU cycle
+        pub fn clock(&mut self) {
+            self.cycle += 1;
+
+            self.triangle.clock_timer();
+            self.noise.clock_timer();
+            self.dmc.clock_timer();
+            if self.cycle.is_multiple_of(2) {
+                self.pulse_1.clock_timer();
+                self.pulse_2.clock_timer();
+            }
+
+            self.clock_frame_counter();
+
+            self.sample_clock += SAMPLE_RATE;
+            if self.sample_clock >= self.cpu_frequency {
+                self.sample_clock -= self.cpu_frequency;
+                let sample = self.mix();
+                self.push_sample(sample);
+            }
+        }
+
+
+        fn push_sample(&mut self, sample: f32) {
+            if self.samples.len() == MAX_BUFFERED_SAMPLES {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(sample);
+        }
+
+
+        /*
+            4-step mode: quarter frames at steps 1-4, half frames at 2 and 4, IRQ at the end unless inhibited
+            5-step mode: quarter frames at steps 1, 2, 3 and 5, half frames at 2 and 5, never an IRQ
+        */
+        fn clock_frame_counter(&mut self) {
+            if self.frame_reset_delay > 0 {
+                self.frame_reset_delay -= 1;
+                if self.frame_reset_delay == 0 {
+                    self.frame_cycle = 0;
+                    //Switching to 5-step mode clocks everything immediately
+                    if self.five_step_mode {
+                        self.quarter_frame();
+                        self.half_frame();
+                    }
+                    return;
+                }
+            }
+
+            self.frame_cycle += 1;
+            let [step_1, step_2, step_3, step_4, step_5] = self.frame_steps;
+            match self.frame_cycle {
+                c if c == step_1 || c == step_3 => self.quarter_frame(),
+                c if c == step_2 => {
+                    self.quarter_frame();
+                    self.half_frame();
+                },
+                //The IRQ flag is raised on the three cycles around the last step
+                c if !self.five_step_mode && (step_4 - 1..=step_4 + 1).contains(&c) => {
+                    if !self.irq_inhibit {
+                        self.frame_irq = true;
+                    }
+                    if c == step_4 {
+                        self.quarter_frame();
+                        self.half_frame();
+                    }
+                    if c == step_4 + 1 {
+                        self.frame_cycle = 0;
+                    }
+                },
+                c if self.five_step_mode && c == step_5 => {
+                    self.quarter_frame();
+                    self.half_frame();
+                },
+                c if self.five_step_mode && c > step_5 => self.frame_cycle = 0,
+                _ => ()
+            }
+        }
+
+
+        //Envelopes and the triangle's linear counter
+        fn quarter_frame(&mut self) {
+            self.pulse_1.envelope.clock();
+            self.pulse_2.envelope.clock();
+            self.noise.envelope.clock();
+            self.triangle.clock_linear();
+        }
+
+
+        //Length counters and sweep units
+        fn half_frame(&mut self) {
+            self.pulse_1.length.clock();
+            self.pulse_2.length.clock();
+            self.triangle.length.clock();
+            self.noise.length.clock();
+            self.pulse_1.clock_sweep();
+            self.pulse_2.clock_sweep();
+        }
+
+
+        //Non-linear mixer approximation from the NESdev wiki
+        fn mix(&self) -> f32 {
+            let pulse = (self.pulse_1.output() + self.pulse_2.output()) as f32;
+            let pulse_out = if pulse == 0.0 {0.0} else {95.88 / (8128.0 / pulse + 100.0)};
+
+            let tnd = self.triangle.output() as f32 / 8227.0 + self.noise.output() as f32 / 12241.0 + self.dmc.output_level as f32 / 22638.0;
+            let tnd_out = if tnd == 0.0 {0.0} else {159.79 / (1.0 / tnd + 100.0)};
+
+            pulse_out + tnd_out + self.expansion_output
+        }
+    }
+
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn sample_buffer_drops_oldest_samples() {
+            let mut apu = Ricoh2a03::new();
+            for i in 0..MAX_BUFFERED_SAMPLES + 10 {
+                apu.push_sample(i as f32);
+            }
+            let samples = apu.take_samples();
+            assert_eq!(samples.len(), MAX_BUFFERED_SAMPLES);
+            assert_eq!(samples[0], 10.0);
+            assert_eq!(samples[MAX_BUFFERED_SAMPLES - 1], (MAX_BUFFERED_SAMPLES + 9) as f32);
+            assert!(apu.take_samples().is_empty());
+        }
+
+        #[test]
+        fn undrained_samples_stop_growing() {
+            let mut apu = Ricoh2a03::new();
+            for _ in 0..2 * NTSC_CPU_FREQUENCY as u32 {
+                apu.clock();
+            }
+            assert_eq!(apu.take_samples().len(), MAX_BUFFERED_SAMPLES);
+        }
+    }
+}
@@ -0,0 +1,53 @@
+pub mod axrom {
+    use crate::cartridge::cartridge::{Mapper, Mapped, Mirroring};
+    use crate::savestate::savestate::stateful;
+
+    //AxROM (mapper 7), switchable 32KB PRG bank, bit 4 picks the nametable for single-screen mirroring
+    pub struct Axrom {
+        prg_size: usize,
+        prg_bank: u8,
+        nametable_b: bool,
+        bus_conflicts: bool
+    }
+
+    stateful!(Axrom { prg_bank, nametable_b });
+
+    impl Mapper for Axrom {
+        fn cpu_read(&mut self, addr: u16) -> Mapped {
+            match addr {
+                0x8000..=0xFFFF => Mapped::PrgRom((self.prg_bank as usize * 0x8000 + (addr & 0x7FFF) as usize) % self.prg_size.max(1)),
+                _ => Mapped::None
+            }
+        }
+        fn cpu_write(&mut self, addr: u16, value: u8) -> Mapped {
+            if addr >= 0x8000 {
+                self.prg_bank = value & 0x07;
+                self.nametable_b = value & 0x10 != 0;
+            }
+            Mapped::None
+        }
+        fn ppu_read(&mut self, addr: u16) -> Mapped {
+            Mapped::Chr(addr as usize)
+        }
+        fn ppu_write(&mut self, addr: u16, _value: u8) -> Mapped {
+            Mapped::Chr(addr as usize)
+        }
+        fn mirroring(&self) -> Mirroring {
+            if self.nametable_b {Mirroring::SingleScreenB} else {Mirroring::SingleScreenA}
+        }
+        fn bus_conflicts(&self) -> bool {
+            self.bus_conflicts
+        }
+    }
+
+    impl Axrom {
+        pub fn new(prg_size: usize, bus_conflicts: bool) -> Axrom {
+            Axrom {
+                prg_size,
+                prg_bank: 0,
+                nametable_b: false,
+                bus_conflicts
+            }
+        }
+    }
+}
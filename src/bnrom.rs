@@ -0,0 +1,82 @@
+pub mod bnrom {
+    use crate::cartridge::cartridge::{Mapper, Mapped, Mirroring};
+    use crate::savestate::savestate::stateful;
+
+    /*
+        Mapper 34 covers two unrelated boards, NES 2.0 tells them apart by submapper, iNES by whether there is CHR ROM:
+        BNROM       32KB PRG bank selected by writes to $8000-$FFFF, 8KB CHR RAM, bus conflicts
+        NINA-001    $7FFD 32KB PRG bank, $7FFE 4KB CHR bank at $0000, $7FFF 4KB CHR bank at $1000.
+                    The registers sit on top of 8KB PRG RAM, writes reach both
+    */
+    pub struct Bnrom {
+        prg_size: usize,
+        chr_size: usize,
+        nina: bool,
+        prg_bank: u8,
+        chr_banks: [u8; 2],
+        mirroring: Mirroring
+    }
+
+    stateful!(Bnrom { prg_bank, chr_banks });
+
+    impl Mapper for Bnrom {
+        fn cpu_read(&mut self, addr: u16) -> Mapped {
+            match addr {
+                0x6000..=0x7FFF if self.nina => Mapped::PrgRam((addr - 0x6000) as usize),
+                0x8000..=0xFFFF => Mapped::PrgRom((self.prg_bank as usize * 0x8000 + (addr & 0x7FFF) as usize) % self.prg_size.max(1)),
+                _ => Mapped::None
+            }
+        }
+        fn cpu_write(&mut self, addr: u16, value: u8) -> Mapped {
+            match addr {
+                0x6000..=0x7FFF if self.nina => {
+                    match addr {
+                        0x7FFD => self.prg_bank = value & 0x01,
+                        0x7FFE => self.chr_banks[0] = value & 0x0F,
+                        0x7FFF => self.chr_banks[1] = value & 0x0F,
+                        _ => ()
+                    }
+                    Mapped::PrgRam((addr - 0x6000) as usize)
+                },
+                0x8000..=0xFFFF if !self.nina => {
+                    self.prg_bank = value;
+                    Mapped::None
+                },
+                _ => Mapped::None
+            }
+        }
+        fn ppu_read(&mut self, addr: u16) -> Mapped {
+            Mapped::Chr(self.chr_address(addr))
+        }
+        fn ppu_write(&mut self, addr: u16, _value: u8) -> Mapped {
+            Mapped::Chr(self.chr_address(addr))
+        }
+        fn mirroring(&self) -> Mirroring {
+            self.mirroring
+        }
+        fn bus_conflicts(&self) -> bool {
+            !self.nina
+        }
+    }
+
+    impl Bnrom {
+        pub fn new(prg_size: usize, chr_size: usize, nina: bool, mirroring: Mirroring) -> Bnrom {
+            Bnrom {
+                prg_size,
+                chr_size: chr_size.max(0x2000),
+                nina,
+                prg_bank: 0,
+                chr_banks: [0, 1],
+                mirroring
+            }
+        }
+
+        fn chr_address(&self, addr: u16) -> usize {
+            if !self.nina {
+                return addr as usize;
+            }
+            let bank = self.chr_banks[(addr >> 12) as usize & 0x01] as usize;
+            (bank * 0x1000 + (addr & 0x0FFF) as usize) % self.chr_size
+        }
+    }
+}
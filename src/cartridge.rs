@@ -1,84 +1,418 @@
 pub mod cartridge {
-    use std::fs::File;
-    use std::io::{Read, Seek, SeekFrom};
+    use std::fs::{self, File};
+    use std::error::Error;
+    use std::fmt;
+    use std::io::{self, Cursor, Read, Seek, SeekFrom};
+    use std::path::{Path, PathBuf};
     use crate::nrom::nrom::Nrom;
+    use crate::mmc1::mmc1::Mmc1;
+    use crate::uxrom::uxrom::Uxrom;
+    use crate::cnrom::cnrom::Cnrom;
+    use crate::axrom::axrom::Axrom;
+    use crate::gxrom::gxrom::Gxrom;
+    use crate::bnrom::bnrom::Bnrom;
+    use crate::mmc3::mmc3::Mmc3;
+    use crate::mmc5::mmc5::Mmc5;
+    use crate::mmc2::mmc2::Mmc2;
+    use crate::vrc4::vrc4::Vrc4;
+    use crate::vrc6::vrc6::Vrc6;
+    use crate::vrc7::vrc7::Vrc7;
+    use crate::header::header::RomHeader;
+    use crate::savestate::savestate::{self, StateError, StateReader, StateWriter, Stateful};
 
-    pub trait Mapper {
-        fn ppu_read(&self) -> usize;
-        fn cpu_read(&self, addr: u16) -> usize;
-        fn cpu_write(&self, addr: u16, value: u8);
-        fn ppu_write(&self);
+    //Nametable layout, which of the 4 logical nametables share the same 1KB of memory
+    #[derive(Debug, Copy, Clone, PartialEq)]
+    pub enum Mirroring {
+        Horizontal,
+        Vertical,
+        SingleScreenA,
+        SingleScreenB,
+        FourScreen,
+        Custom([u8; 4]), //CIRAM page for each nametable, set by the mapper
     }
 
+    //Where a mapper routed a bus access, offsets are into the Cartridge's memory
+    #[derive(Debug, Copy, Clone, PartialEq)]
+    pub enum Mapped {
+        PrgRom(usize),
+        PrgRam(usize),
+        Chr(usize),
+        Value(u8), //Mapper register or other internal value
+        None //Nothing responds, open bus
+    }
+
+    /*
+        Mappers translate CPU ($4020-$FFFF) and PPU ($0000-$1FFF) addresses into the cartridge's memory and
+        keep whatever bank registers they need. Writes return where the value should be stored, Mapped::None
+        when the mapper consumed it as a register write.
+        ppu_address sees every address the PPU puts on its bus, pattern, nametable and palette alike, which is
+        all a mapper needs to watch A12 edges or detect scanlines from the fetch pattern.
+        cpu_clock runs once per CPU cycle for mappers that count cycles.
+        Every mapper is Stateful so save states can capture its bank registers, IRQ counters and audio.
+        nametable_read/nametable_write let a mapper supply nametable data itself instead of CIRAM,
+        ppu_register_write sees CPU writes to $2000-$3FFF for mappers that snoop PPUCTRL and PPUMASK.
+        audio_output is the expansion audio level, in the same scale as the APU's mixer output.
+        Boards with bus conflicts have the ROM drive the bus during register writes, the register gets
+        the written value ANDed with the ROM byte at that address.
+    */
+    pub trait Mapper: Stateful {
+        fn cpu_read(&mut self, addr: u16) -> Mapped;
+        fn cpu_write(&mut self, addr: u16, value: u8) -> Mapped;
+        fn ppu_read(&mut self, addr: u16) -> Mapped;
+        fn ppu_write(&mut self, addr: u16, value: u8) -> Mapped;
+        fn mirroring(&self) -> Mirroring;
+        fn irq(&self) -> bool { false }
+        fn ppu_address(&mut self, _addr: u16) {}
+        fn cpu_clock(&mut self) {}
+        fn bus_conflicts(&self) -> bool { false }
+        fn nametable_read(&mut self, _addr: u16) -> Option<u8> { None }
+        fn nametable_write(&mut self, _addr: u16, _value: u8) -> bool { false }
+        fn ppu_register_write(&mut self, _addr: u16, _value: u8) {}
+        fn audio_output(&self) -> f32 { 0.0 }
+    }
+
+    //Why a ROM couldn't be loaded, sizes are in bytes
+    #[derive(Debug)]
+    pub enum RomLoadError {
+        Io(io::Error),
+        BadMagic,
+        TruncatedPrg { expected: usize, actual: usize },
+        TruncatedChr { expected: usize, actual: usize },
+        UnsupportedMapper(u16),
+        InvalidNes2Field { field: &'static str, value: u8 },
+    }
+
+    impl fmt::Display for RomLoadError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                RomLoadError::Io(err) => write!(f, "I/O error: {}", err),
+                RomLoadError::BadMagic => write!(f, "Bad iNES header"),
+                RomLoadError::TruncatedPrg { expected, actual } => write!(f, "PRG ROM truncated, expected {} bytes, found {}", expected, actual),
+                RomLoadError::TruncatedChr { expected, actual } => write!(f, "CHR ROM truncated, expected {} bytes, found {}", expected, actual),
+                RomLoadError::UnsupportedMapper(mapper) => write!(f, "Unsupported mapper {}", mapper),
+                RomLoadError::InvalidNes2Field { field, value } => write!(f, "Invalid NES 2.0 {} ${:02X}", field, value)
+            }
+        }
+    }
+
+    impl Error for RomLoadError {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            match self {
+                RomLoadError::Io(err) => Some(err),
+                _ => None
+            }
+        }
+    }
+
+    //I/O failures, including a file too short to hold the header
+    impl From<io::Error> for RomLoadError {
+        fn from(err: io::Error) -> Self {
+            RomLoadError::Io(err)
+        }
+    }
+
+
     pub struct Cartridge {
         pub mapper: Box<dyn Mapper>,
         pub prg_rom: Vec<u8>,
-        pub chr_rom: Vec<u8>,
+        pub prg_ram: Vec<u8>,
+        pub chr_rom: Vec<u8>, //CHR RAM when chr_ram is set
+        pub chr_ram: bool,
+        pub header: RomHeader,
+
+        //Battery backed PRG RAM is kept in a .sav file next to the ROM, None without a battery
+        save_path: Option<PathBuf>,
+        save_dirty: bool,
     }
 
 
     impl Cartridge {
 
-        pub fn load_rom(file_name: &String) -> Result<Cartridge, String> {
+        //Loads a ROM without reporting anything, see load_rom_logged
+        pub fn load_rom(file_name: impl AsRef<Path>) -> Result<Cartridge, RomLoadError> {
+            Self::load_rom_logged(file_name, &mut |_| ())
+        }
+
+
+        //Loads a ROM, the progress messages go to log instead of stdout. Battery RAM is kept in a .sav next to the ROM
+        pub fn load_rom_logged(file_name: impl AsRef<Path>, log: &mut dyn FnMut(&str)) -> Result<Cartridge, RomLoadError> {
             //Open file in read-only mode,
-            let mut file = File::open(file_name).map_err(|_| String::from("Could not open file"))?;
-            println!("Found file: {}", file_name);
+            let file_name = file_name.as_ref();
+            let mut file = File::open(file_name)?;
+            log(&format!("Found file: {}", file_name.display()));
+
+            let mut cart = Self::from_reader_logged(&mut file, log)?;
+
+            if cart.header.battery && !cart.prg_ram.is_empty() {
+                let save_path = file_name.with_extension("sav");
+                if cart.load_save(&save_path)? {
+                    log(&format!("Loaded save file: {}", save_path.display()));
+                }
+                cart.save_path = Some(save_path);
+            }
+
+            Result::Ok(cart)
+        }
+
+
+        //ROM image already in memory, battery RAM isn't persisted
+        pub fn from_bytes(data: &[u8]) -> Result<Cartridge, RomLoadError> {
+            Self::from_reader(&mut Cursor::new(data))
+        }
+
+
+        pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Cartridge, RomLoadError> {
+            Self::from_reader_logged(reader, &mut |_| ())
+        }
+
+
+        //Parses an iNES/NES 2.0 image starting at the reader's current position
+        pub fn from_reader_logged<R: Read + Seek>(reader: &mut R, log: &mut dyn FnMut(&str)) -> Result<Cartridge, RomLoadError> {
+            let start = reader.stream_position()?;
 
             //Read the header
             let mut ines_header: [u8; 16] = [0; 16];
-            file.read_exact(&mut ines_header).map_err(|_| String::from("Could not read header"))?;
-            println!("Read header...");
+            reader.read_exact(&mut ines_header)?;
+            let header = RomHeader::parse(&ines_header)?;
+            log(&format!("Read header, mapper {} submapper {}, {}", header.mapper, header.submapper, if header.nes2 {"NES 2.0"} else {"iNES"}));
 
-            if ines_header[0] != 0x4E || ines_header[1] != 0x45 || ines_header[2] != 0x53 || ines_header[3] != 0x1A {
-                return Err(String::from("Bad iNES header"));
-            }
+            let prg_size = header.prg_rom_size;
+            //Boards without CHR ROM have CHR RAM, 8KB unless the header says otherwise
+            let chr_ram = header.chr_rom_size == 0;
+            let chr_size = if chr_ram {header.total_chr_ram_size().max(0x2000)} else {header.chr_rom_size};
+            let prg_ram_size = header.total_prg_ram_size();
 
             let mut cart = Cartridge {
-                mapper: match (ines_header[6] & 0xF0) >> 4 | ines_header[7] & 0xF0 {
-                    0 => Box::new(Nrom::new(ines_header[4] == 1)),
-                    _ => Box::new(Nrom::new(ines_header[4] == 1))
-                },
-                prg_rom: vec![0],
-                chr_rom: vec![0]
+                mapper: Self::create_mapper(&header, chr_size, chr_ram)?,
+                prg_rom: Vec::new(),
+                prg_ram: vec![0; prg_ram_size],
+                chr_rom: vec![0; if chr_ram {chr_size} else {0}],
+                chr_ram,
+                save_path: None,
+                save_dirty: false,
+                header
             };
-            println!("Mapper loaded");
-
-            //Total size is in bytes
-            cart.prg_rom.resize((ines_header[4] as u64 * 16384) as usize, 0);
-            cart.chr_rom.resize((ines_header[5] as u64 * 8192) as usize, 0);
-            println!("PRG ROM size: {}, CHR ROM size: {}",ines_header[4] as u64 * 16384, ines_header[5] as u64 * 8192);
+            if chr_ram {
+                log(&format!("PRG ROM size: {}, CHR RAM size: {}", prg_size, chr_size));
+            } else {
+                log(&format!("PRG ROM size: {}, CHR ROM size: {}", prg_size, chr_size));
+            }
             
             //Start of the PRG data, taking the trainer into account if present
-            let cpu_start: u64 = 16 + if ines_header[6] & 0x04 != 0 {512} else {0}; 
+            let cpu_start: u64 = 16 + if cart.header.trainer {512} else {0};
 
-            file.seek(SeekFrom::Start(cpu_start)).map_err(|_| String::from("Seek to PRG failed"))?;
-            file.read_exact(&mut cart.prg_rom).map_err(|_| String::from("Read PRG failed"))?;
-            file.seek(SeekFrom::Current(0)).map_err(|_| String::from("Seek to CHR failed"))?;
-            file.read_exact(&mut cart.chr_rom).map_err(|_| String::from("Read CHR failed"))?;
+            reader.seek(SeekFrom::Start(start + cpu_start))?;
+            cart.prg_rom = Self::read_rom(reader, prg_size, |expected, actual| RomLoadError::TruncatedPrg { expected, actual })?;
+            if !chr_ram {
+                cart.chr_rom = Self::read_rom(reader, chr_size, |expected, actual| RomLoadError::TruncatedChr { expected, actual })?;
+            }
             
             Result::Ok(cart)
         }
 
-        pub fn cpu_read(&self, addr: u16) -> u8 {
-            match addr {
-                0x6000..=0x7FFF => 0, //Battery backed save or work RAM
-                0x8000..=0xFFFF => self.prg_rom[self.mapper.cpu_read(addr)], //Cartridge ROM
-                _ => 0 //0x4020..0x5FFF -- Mapper specific
+
+        //Reads up to size bytes, coming up short means the file was cut off
+        fn read_rom(reader: &mut impl Read, size: usize, truncated: fn(usize, usize) -> RomLoadError) -> Result<Vec<u8>, RomLoadError> {
+            let mut data = Vec::new();
+            reader.take(size as u64).read_to_end(&mut data)?;
+            if data.len() < size {
+                return Err(truncated(size, data.len()));
             }
+            Ok(data)
         }
 
-        pub fn cpu_write(&self, addr: u16, value: u8) {
-            self.mapper.cpu_write(addr, value);
+
+        fn create_mapper(header: &RomHeader, chr_size: usize, chr_ram: bool) -> Result<Box<dyn Mapper>, RomLoadError> {
+            let prg_size = header.prg_rom_size;
+            let prg_ram_size = header.total_prg_ram_size();
+            let mirroring = header.mirroring;
+            let submapper = header.submapper;
+
+            //Discrete boards: submapper 1 has no bus conflicts, 2 has them, 0 keeps the usual board's behaviour
+            let bus_conflicts = |usual: bool| match submapper {
+                1 => false,
+                2 => true,
+                _ => usual
+            };
+
+            Ok(match header.mapper {
+                0 => Box::new(Nrom::new(prg_size <= 0x4000, mirroring)),
+                //Submapper 5 is SEROM/SHROM, 32KB of PRG ROM that ignores the PRG bank
+                1 => Box::new(Mmc1::new(prg_size, chr_size, chr_ram, prg_ram_size, submapper == 5)),
+                2 => Box::new(Uxrom::new(prg_size, mirroring, bus_conflicts(true))),
+                3 => Box::new(Cnrom::new(prg_size <= 0x4000, chr_size, mirroring, bus_conflicts(true))),
+                4 => Box::new(Mmc3::new(prg_size, chr_size, mirroring == Mirroring::FourScreen)),
+                5 => Box::new(Mmc5::new(prg_size, chr_size, prg_ram_size)),
+                //Most AxROM games are on AOROM boards, which have no bus conflicts
+                7 => Box::new(Axrom::new(prg_size, bus_conflicts(false))),
+                9 => Box::new(Mmc2::new(prg_size, chr_size, false)),
+                10 => Box::new(Mmc2::new(prg_size, chr_size, true)),
+                21 | 22 | 23 | 25 => Box::new(Vrc4::new(header.mapper, submapper, prg_size, chr_size)),
+                24 => Box::new(Vrc6::new(prg_size, chr_size, false)),
+                26 => Box::new(Vrc6::new(prg_size, chr_size, true)),
+                //Submapper 1 is NINA-001, 2 is BNROM, otherwise only NINA-001 has CHR ROM
+                34 => Box::new(Bnrom::new(prg_size, chr_size, submapper == 1 || (submapper == 0 && !chr_ram), mirroring)),
+                66 => Box::new(Gxrom::new(prg_size, chr_size, mirroring)),
+                85 => Box::new(Vrc7::new(prg_size, chr_size)),
+                mapper => return Err(RomLoadError::UnsupportedMapper(mapper))
+            })
         }
 
-        pub fn ppu_read(&self) {
-            println!("PPU read from cartridge not supported!");
-            panic!();
+
+        //A missing save file just means a fresh game, a short one only fills the start of PRG RAM.
+        //Returns whether there was one
+        fn load_save(&mut self, save_path: &Path) -> io::Result<bool> {
+            if !save_path.exists() {
+                return Ok(false);
+            }
+            let data = fs::read(save_path)?;
+            let length = data.len().min(self.prg_ram.len());
+            self.prg_ram[..length].copy_from_slice(&data[..length]);
+            Ok(true)
+        }
+
+
+        //Write battery backed PRG RAM out if it changed since the last flush
+        pub fn flush_save(&mut self) -> io::Result<()> {
+            if !self.save_dirty {
+                return Ok(());
+            }
+            if let Some(save_path) = &self.save_path {
+                fs::write(save_path, &self.prg_ram)?;
+            }
+            self.save_dirty = false;
+            Ok(())
+        }
+
+        //Out of range offsets read as 0 rather than panicking on a bad mapper or undersized ROM
+        fn mapped_read(&self, mapped: Mapped) -> u8 {
+            match mapped {
+                Mapped::PrgRom(offset) => self.prg_rom.get(offset).copied().unwrap_or(0),
+                Mapped::PrgRam(offset) => self.prg_ram.get(offset).copied().unwrap_or(0),
+                Mapped::Chr(offset) => self.chr_rom.get(offset).copied().unwrap_or(0),
+                Mapped::Value(value) => value,
+                Mapped::None => 0
+            }
+        }
+
+
+        //$4020-$FFFF: PRG ROM, PRG RAM at $6000-$7FFF and mapper registers
+        pub fn cpu_read(&mut self, addr: u16) -> u8 {
+            let mapped = self.mapper.cpu_read(addr);
+            self.mapped_read(mapped)
+        }
+
+
+        pub fn cpu_write(&mut self, addr: u16, value: u8) {
+            let value = if addr >= 0x8000 && self.mapper.bus_conflicts() {
+                let mapped = self.mapper.cpu_read(addr);
+                value & self.mapped_read(mapped)
+            } else {
+                value
+            };
+            if let Mapped::PrgRam(offset) = self.mapper.cpu_write(addr, value) {
+                if let Some(byte) = self.prg_ram.get_mut(offset) {
+                    self.save_dirty |= *byte != value;
+                    *byte = value;
+                }
+            }
+        }
+
+
+        //Pattern table reads, $0000-$1FFF
+        pub fn ppu_read(&mut self, addr: u16) -> u8 {
+            let mapped = self.mapper.ppu_read(addr);
+            self.mapped_read(mapped)
+        }
+
+
+        //Pattern table writes, CHR ROM ignores them
+        pub fn ppu_write(&mut self, addr: u16, value: u8) {
+            if let Mapped::Chr(offset) = self.mapper.ppu_write(addr, value) {
+                if self.chr_ram {
+                    if let Some(byte) = self.chr_rom.get_mut(offset) {
+                        *byte = value;
+                    }
+                }
+            }
+        }
+
+
+        pub fn mirroring(&self) -> Mirroring {
+            self.mapper.mirroring()
+        }
+
+
+        pub fn irq(&self) -> bool {
+            self.mapper.irq()
+        }
+
+
+        pub fn ppu_address(&mut self, addr: u16) {
+            self.mapper.ppu_address(addr);
+        }
+
+
+        pub fn cpu_clock(&mut self) {
+            self.mapper.cpu_clock();
+        }
+
+
+        pub fn nametable_read(&mut self, addr: u16) -> Option<u8> {
+            self.mapper.nametable_read(addr)
+        }
+
+
+        pub fn nametable_write(&mut self, addr: u16, value: u8) -> bool {
+            self.mapper.nametable_write(addr, value)
+        }
+
+
+        pub fn ppu_register_write(&mut self, addr: u16, value: u8) {
+            self.mapper.ppu_register_write(addr, value);
+        }
+
+
+        pub fn audio_output(&self) -> f32 {
+            self.mapper.audio_output()
+        }
+
+
+        //Identifies the ROM in save states, CHR RAM is machine state and not part of it
+        pub fn rom_hash(&self) -> u64 {
+            let chr_rom: &[u8] = if self.chr_ram {&[]} else {&self.chr_rom};
+            savestate::rom_hash(&[&self.prg_rom, chr_rom])
         }
     }
-    
 
 
+    impl Stateful for Cartridge {
+        fn save(&self, state: &mut StateWriter) {
+            self.prg_ram.save(state);
+            if self.chr_ram {
+                self.chr_rom.save(state);
+            }
+            self.mapper.save(state);
+        }
 
+        //Battery RAM restored from a state is what the game sees now, so it goes to the .sav on the next flush
+        fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+            self.prg_ram.load(state)?;
+            if self.chr_ram {
+                self.chr_rom.load(state)?;
+            }
+            self.mapper.load(state)?;
+            self.save_dirty = self.save_path.is_some();
+            Ok(())
+        }
+    }
 
+
+    //Last chance to keep the player's progress. There is nobody to report a failure to here,
+    //call flush_save before dropping to find out whether it worked
+    impl Drop for Cartridge {
+        fn drop(&mut self) {
+            let _ = self.flush_save();
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,54 @@
+pub mod cnrom {
+    use crate::cartridge::cartridge::{Mapper, Mapped, Mirroring};
+    use crate::savestate::savestate::stateful;
+
+    //CNROM (mapper 3), NROM PRG layout with a switchable 8KB CHR bank
+    pub struct Cnrom {
+        prg_bank_mirror: u16, //One 32KB bank or two 16KB banks where 0xC000-0xFFFF mirrors 0x8000-0xBFFF
+        chr_size: usize,
+        chr_bank: u8,
+        mirroring: Mirroring,
+        bus_conflicts: bool
+    }
+
+    stateful!(Cnrom { chr_bank });
+
+    impl Mapper for Cnrom {
+        fn cpu_read(&mut self, addr: u16) -> Mapped {
+            match addr {
+                0x8000..=0xFFFF => Mapped::PrgRom(((addr & self.prg_bank_mirror) - 0x8000) as usize),
+                _ => Mapped::None
+            }
+        }
+        fn cpu_write(&mut self, addr: u16, value: u8) -> Mapped {
+            if addr >= 0x8000 {
+                self.chr_bank = value;
+            }
+            Mapped::None
+        }
+        fn ppu_read(&mut self, addr: u16) -> Mapped {
+            Mapped::Chr((self.chr_bank as usize * 0x2000 + addr as usize) % self.chr_size)
+        }
+        fn ppu_write(&mut self, addr: u16, _value: u8) -> Mapped {
+            Mapped::Chr((self.chr_bank as usize * 0x2000 + addr as usize) % self.chr_size)
+        }
+        fn mirroring(&self) -> Mirroring {
+            self.mirroring
+        }
+        fn bus_conflicts(&self) -> bool {
+            self.bus_conflicts
+        }
+    }
+
+    impl Cnrom {
+        pub fn new(prg_mirrored: bool, chr_size: usize, mirroring: Mirroring, bus_conflicts: bool) -> Cnrom {
+            Cnrom {
+                prg_bank_mirror: if prg_mirrored {0xBFFF} else {0xFFFF},
+                chr_size: chr_size.max(0x2000),
+                chr_bank: 0,
+                mirroring,
+                bus_conflicts
+            }
+        }
+    }
+}
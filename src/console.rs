@@ -1,6 +1,16 @@
 pub mod console {
 
+    use std::io;
     use crate::cpu::cpu::Mos6502;
+    use crate::controller::controller::ControllerPort;
+    use crate::savestate::savestate::{StateError, StateReader, StateWriter, Stateful};
+
+    /*
+        Battery saves are flushed every 5 seconds of NTSC frames, and again when the emulation stops or the cartridge
+        is dropped. Neither runs if the process is killed, Ctrl+C included, so up to 300 frames of progress can be lost.
+        A frontend that wants to avoid that calls flush_save from its own shutdown or signal handling.
+    */
+    const SAVE_INTERVAL: u64 = 300;
 
     pub struct Console<'a> {
         pub cpu: &'a mut Mos6502<'a>
@@ -9,17 +19,182 @@ pub mod console {
 
     impl Console<'_> {
 
-        pub fn start_console(&mut self) {
+        //Runs until the CPU jams, then writes out battery RAM and returns whether that worked
+        pub fn start_console(&mut self) -> io::Result<()> {
             //Perform a reset on the cpu and ppu
             self.cpu.reset(true);
 
-            //Execute a single CPU instruction and receive the cycle count
-            //Run the PPU for 3 times that many cycles
             loop {
-                let cpu_cycles = self.cpu.execute_instruction();
-                if cpu_cycles == 0 {break;}
-                self.cpu.ppu.generate_signal(cpu_cycles as u16);
+                let frame = self.cpu.ppu.frame_count();
+                if self.step() == 0 {break;}
+                if self.cpu.ppu.frame_count() != frame {
+                    self.end_frame();
+                }
+            }
+            self.flush_save()
+        }
+
+        //Run until the PPU completes a frame, returns the frame as 6-bit NES colors
+        pub fn run_frame(&mut self) -> &[u8] {
+            let frame = self.cpu.ppu.frame_count();
+            while self.cpu.ppu.frame_count() == frame {
+                if self.step() == 0 {break;}
+            }
+            self.end_frame();
+            self.cpu.ppu.frame_buffer()
+        }
+
+
+        //A failed periodic flush leaves the RAM marked dirty, so it's retried at the next interval
+        fn end_frame(&mut self) {
+            if self.cpu.ppu.frame_count().is_multiple_of(SAVE_INTERVAL) {
+                let _ = self.flush_save();
+            }
+        }
+
+
+        //Write battery backed RAM to the .sav file now if it changed
+        pub fn flush_save(&mut self) -> io::Result<()> {
+            self.cpu.cart.borrow_mut().flush_save()
+        }
+
+
+        //Audio produced since the last call, mono at apu::SAMPLE_RATE. Drain it after each run_frame,
+        //undrained audio is capped at one second
+        pub fn take_samples(&mut self) -> Vec<f32> {
+            self.cpu.apu.take_samples()
+        }
+
+
+        //Button state for a port (0 or 1) as controller::BUTTON_ flags, set it before each run_frame
+        pub fn set_buttons(&mut self, port: usize, buttons: u8) {
+            self.cpu.controllers[port].set_buttons(buttons);
+        }
+
+        //Replace the device plugged into a port, both start with a standard joypad
+        pub fn connect(&mut self, port: usize, device: Box<dyn ControllerPort>) {
+            self.cpu.controllers[port] = device;
+        }
+
+        //Snapshot of the whole machine, tagged with the ROM's hash. Take it between instructions, e.g. after run_frame
+        pub fn save_state(&self) -> Vec<u8> {
+            let mut state = StateWriter::new(self.cpu.cart.borrow().rom_hash());
+            self.cpu.save(&mut state);
+            state.finish()
+        }
+
+
+        //Restores a snapshot from save_state, on any error the machine is left as it was
+        pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+            let rom_hash = self.cpu.cart.borrow().rom_hash();
+            let mut state = StateReader::new(data, rom_hash)?;
+            let backup = self.save_state();
+            let result = self.cpu.load(&mut state).and_then(|_| state.finish());
+            if result.is_err() {
+                let mut restore = StateReader::new(&backup, rom_hash)?;
+                self.cpu.load(&mut restore)?;
+            }
+            result
+        }
+
+
+        //Execute a single CPU instruction and receive the cycle count
+        //The CPU runs the PPU itself, 3 PPU cycles for every bus access
+        pub fn step(&mut self) -> u16 {
+            self.cpu.execute_instruction()
+        }
+    }
+
+
+    #[cfg(test)]
+    mod tests {
+        use std::cell::RefCell;
+        use super::*;
+        use crate::cartridge::cartridge::Cartridge;
+        use crate::ppu::ppu::Ricoh2c02;
+        use crate::savestate::savestate::VERSION;
+
+        //NROM with 16KB PRG and 8KB CHR whose program counts up $00 forever, the value gives each ROM its own hash
+        fn rom(value: u8) -> Vec<u8> {
+            let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+            let mut prg = vec![value; 0x4000];
+            //$C000: INC $00, JMP $C000
+            prg[..5].copy_from_slice(&[0xE6, 0x00, 0x4C, 0x00, 0xC0]);
+            prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0xC0]);
+            data.extend(prg);
+            data.extend(vec![0; 0x2000]);
+            data
+        }
+
+        //Builds a console for the ROM, runs a few frames and hands it to the test
+        fn with_console(value: u8, test: impl FnOnce(&mut Console)) {
+            let cart = RefCell::new(Cartridge::from_bytes(&rom(value)).unwrap());
+            let mut ppu = Ricoh2c02::new(&cart);
+            let mut cpu = Mos6502::new(&cart, &mut ppu);
+            let mut nes = Console { cpu: &mut cpu };
+            nes.cpu.reset(true);
+            for _ in 0..3 {
+                nes.run_frame();
             }
+            test(&mut nes);
+        }
+
+        #[test]
+        fn save_load_save_round_trip() {
+            with_console(0xEA, |nes| {
+                let state = nes.save_state();
+                nes.run_frame();
+                assert_ne!(nes.save_state(), state);
+                assert_eq!(nes.load_state(&state), Ok(()));
+                assert_eq!(nes.save_state(), state);
+            });
+        }
+
+        #[test]
+        fn bad_magic_is_rejected() {
+            with_console(0xEA, |nes| {
+                let mut state = nes.save_state();
+                state[0] = b'X';
+                assert_eq!(nes.load_state(&state), Err(StateError::BadMagic));
+            });
+        }
+
+        #[test]
+        fn other_versions_are_rejected() {
+            with_console(0xEA, |nes| {
+                let mut state = nes.save_state();
+                state[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+                assert_eq!(nes.load_state(&state), Err(StateError::UnsupportedVersion(VERSION + 1)));
+            });
+        }
+
+        #[test]
+        fn states_from_other_roms_are_rejected() {
+            let mut other = Vec::new();
+            with_console(0xEB, |nes| other = nes.save_state());
+            with_console(0xEA, |nes| {
+                let rom_hash = nes.cpu.cart.borrow().rom_hash();
+                let found = u64::from_le_bytes(other[6..14].try_into().unwrap());
+                assert_eq!(nes.load_state(&other), Err(StateError::RomMismatch { expected: rom_hash, found }));
+            });
+        }
+
+        #[test]
+        fn failed_loads_leave_the_machine_unchanged() {
+            with_console(0xEA, |nes| {
+                let state = nes.save_state();
+                nes.run_frame();
+                let current = nes.save_state();
+
+                //Cut off in the controllers, everything before them has already been loaded by then
+                assert_eq!(nes.load_state(&state[..state.len() - 1]), Err(StateError::Truncated));
+                assert_eq!(nes.save_state(), current);
+
+                let mut longer = state.clone();
+                longer.push(0);
+                assert_eq!(nes.load_state(&longer), Err(StateError::Invalid("trailing data")));
+                assert_eq!(nes.save_state(), current);
+            });
         }
     }
-}
\ No newline at end of file
+}
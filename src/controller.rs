@@ -0,0 +1,118 @@
+pub mod controller {
+    use crate::savestate::savestate::{stateful, StateError, StateReader, StateWriter, Stateful};
+
+    //Standard joypad buttons, in the order they are shifted out
+    pub const BUTTON_A: u8 = 0x01;
+    pub const BUTTON_B: u8 = 0x02;
+    pub const BUTTON_SELECT: u8 = 0x04;
+    pub const BUTTON_START: u8 = 0x08;
+    pub const BUTTON_UP: u8 = 0x10;
+    pub const BUTTON_DOWN: u8 = 0x20;
+    pub const BUTTON_LEFT: u8 = 0x40;
+    pub const BUTTON_RIGHT: u8 = 0x80;
+
+    /*
+        Anything plugged into $4016/$4017. Both ports share the strobe written to bit 0 of $4016,
+        read returns the low bits the device drives, the CPU fills the rest from open bus.
+        Devices with internal latches keep them in save states through save_state/load_state.
+    */
+    pub trait ControllerPort {
+        fn read(&mut self) -> u8;
+        fn write(&mut self, strobe: bool);
+        fn set_buttons(&mut self, buttons: u8);
+        fn save_state(&self, _state: &mut StateWriter) {}
+        fn load_state(&mut self, _state: &mut StateReader) -> Result<(), StateError> { Ok(()) }
+    }
+
+
+    //Empty port, nothing drives the data lines
+    pub struct Unplugged;
+
+    impl ControllerPort for Unplugged {
+        fn read(&mut self) -> u8 {
+            0
+        }
+
+        fn write(&mut self, _strobe: bool) {}
+
+        fn set_buttons(&mut self, _buttons: u8) {}
+    }
+
+
+    /*
+        Standard controller, a 4021 shift register latched from the buttons while strobe is high.
+        While strobe is high every read returns A, after all 8 buttons have been read an official
+        controller returns 1.
+    */
+    pub struct Joypad {
+        buttons: u8,
+        shift_register: u8,
+        reads: u8,
+        strobe: bool,
+    }
+
+    stateful!(Joypad { shift_register, reads, strobe });
+
+
+    impl Default for Joypad {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+
+    impl Joypad {
+        pub fn new() -> Joypad {
+            Joypad {
+                buttons: 0,
+                shift_register: 0,
+                reads: 0,
+                strobe: false,
+            }
+        }
+
+        fn latch(&mut self) {
+            self.shift_register = self.buttons;
+            self.reads = 0;
+        }
+    }
+
+
+    impl ControllerPort for Joypad {
+        fn read(&mut self) -> u8 {
+            if self.strobe {
+                self.latch();
+                return self.shift_register & 0x01;
+            }
+            if self.reads >= 8 {
+                return 0x01;
+            }
+            let bit = self.shift_register & 0x01;
+            self.shift_register >>= 1;
+            self.reads += 1;
+            bit
+        }
+
+        fn write(&mut self, strobe: bool) {
+            self.strobe = strobe;
+            if strobe {
+                self.latch();
+            }
+        }
+
+        fn set_buttons(&mut self, buttons: u8) {
+            self.buttons = buttons;
+            if self.strobe {
+                self.latch();
+            }
+        }
+
+        fn save_state(&self, state: &mut StateWriter) {
+            self.save(state);
+        }
+
+        fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+            self.load(state)
+        }
+    }
+}
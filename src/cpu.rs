@@ -1,10 +1,18 @@
 
 pub mod cpu {
 
+    use std::cell::RefCell;
     use std::fs::File;
     use std::io::Write;
     use crate::cartridge::cartridge;
     use crate::ppu::ppu::Ricoh2c02;
+    use crate::apu::apu::Ricoh2a03;
+    use crate::controller::controller::{ControllerPort, Joypad};
+    use crate::header::header::Region;
+    use crate::savestate::savestate::{StateError, StateReader, StateWriter, Stateful};
+
+    //Value ORed into the accumulator by the unstable XAA and LAX immediate opcodes, varies between chips
+    const UNSTABLE_MAGIC: u8 = 0xEE;
 
     #[derive(Debug, Copy, Clone)]
     enum AddressingMode {
@@ -24,7 +32,8 @@ pub mod cpu {
     }
 
     /*
-        All MOS6502 instructions minus 'unofficial' opcodes, which have been replaced with NAI = Not An Instruction
+        All MOS6502 instructions, the 'unofficial' opcodes follow TYA. Names for those vary between sources,
+        the ones used here match nestest.log where possible. KIL halts the CPU until the next reset.
      */
     enum Instruction {
         ADC(AddressingMode),AND(AddressingMode),ASL(AddressingMode),BCC(AddressingMode),BCS(AddressingMode),
@@ -38,12 +47,62 @@ pub mod cpu {
         ROR(AddressingMode),RTI(AddressingMode),RTS(AddressingMode),SBC(AddressingMode),SEC(AddressingMode),
         SED(AddressingMode),SEI(AddressingMode),STA(AddressingMode),STX(AddressingMode),STY(AddressingMode),
         TAX(AddressingMode),TAY(AddressingMode),TSX(AddressingMode),TXA(AddressingMode),TXS(AddressingMode),
-        TYA(AddressingMode),NAI
+        TYA(AddressingMode),
+        ALR(AddressingMode),ANC(AddressingMode),ARR(AddressingMode),AXS(AddressingMode),DCP(AddressingMode),
+        ISC(AddressingMode),KIL(AddressingMode),LAS(AddressingMode),LAX(AddressingMode),RLA(AddressingMode),
+        RRA(AddressingMode),SAX(AddressingMode),SHA(AddressingMode),SHX(AddressingMode),SHY(AddressingMode),
+        SLO(AddressingMode),SRE(AddressingMode),TAS(AddressingMode),XAA(AddressingMode)
+    }
+
+
+    impl Instruction {
+        fn addressing_mode(&self) -> AddressingMode {
+            match self {
+                Instruction::ADC(mode) | Instruction::AND(mode) | Instruction::ASL(mode) | Instruction::BCC(mode) | Instruction::BCS(mode) |
+                Instruction::BEQ(mode) | Instruction::BIT(mode) | Instruction::BMI(mode) | Instruction::BNE(mode) | Instruction::BPL(mode) |
+                Instruction::BRK(mode) | Instruction::BVC(mode) | Instruction::BVS(mode) | Instruction::CLC(mode) | Instruction::CLD(mode) |
+                Instruction::CLI(mode) | Instruction::CLV(mode) | Instruction::CMP(mode) | Instruction::CPX(mode) | Instruction::CPY(mode) |
+                Instruction::DEC(mode) | Instruction::DEX(mode) | Instruction::DEY(mode) | Instruction::EOR(mode) | Instruction::INC(mode) |
+                Instruction::INX(mode) | Instruction::INY(mode) | Instruction::JMP(mode) | Instruction::JSR(mode) | Instruction::LDA(mode) |
+                Instruction::LDX(mode) | Instruction::LDY(mode) | Instruction::LSR(mode) | Instruction::NOP(mode) | Instruction::ORA(mode) |
+                Instruction::PHA(mode) | Instruction::PHP(mode) | Instruction::PLA(mode) | Instruction::PLP(mode) | Instruction::ROL(mode) |
+                Instruction::ROR(mode) | Instruction::RTI(mode) | Instruction::RTS(mode) | Instruction::SBC(mode) | Instruction::SEC(mode) |
+                Instruction::SED(mode) | Instruction::SEI(mode) | Instruction::STA(mode) | Instruction::STX(mode) | Instruction::STY(mode) |
+                Instruction::TAX(mode) | Instruction::TAY(mode) | Instruction::TSX(mode) | Instruction::TXA(mode) | Instruction::TXS(mode) |
+                Instruction::TYA(mode) | Instruction::ALR(mode) | Instruction::ANC(mode) | Instruction::ARR(mode) | Instruction::AXS(mode) |
+                Instruction::DCP(mode) | Instruction::ISC(mode) | Instruction::KIL(mode) | Instruction::LAS(mode) | Instruction::LAX(mode) |
+                Instruction::RLA(mode) | Instruction::RRA(mode) | Instruction::SAX(mode) | Instruction::SHA(mode) | Instruction::SHX(mode) |
+                Instruction::SHY(mode) | Instruction::SLO(mode) | Instruction::SRE(mode) | Instruction::TAS(mode) | Instruction::XAA(mode) => *mode
+            }
+        }
+
+        //Total size in bytes of the instruction including the opcode
+        fn length(&self) -> u16 {
+            match self.addressing_mode() {
+                AddressingMode::Implied | AddressingMode::Accumulator => 1,
+                AddressingMode::Absolute | AddressingMode::AbsoluteIndexX | 
+                AddressingMode::AbsoluteIndexY | AddressingMode::Indirect => 3,
+                _ => 2
+            }
+        }
+    }
+
+    /*
+        The IRQ line is level triggered and shared, it stays asserted as long as any source holds it.
+        Each source owns one bit of Mos6502::irq_sources.
+    */
+    #[derive(Debug, Copy, Clone)]
+    pub enum IrqSource {
+        FrameCounter = 0x01,
+        Dmc = 0x02,
+        Mapper = 0x04,
     }
 
     pub struct Mos6502<'a> {
-        pub cart: &'a cartridge::Cartridge,
+        pub cart: &'a RefCell<cartridge::Cartridge>,
         pub ppu: &'a mut Ricoh2c02<'a>,
+        pub apu: Ricoh2a03,
+        pub controllers: [Box<dyn ControllerPort>; 2],
 
         //Registers
         acc: u8,
@@ -56,77 +115,141 @@ pub mod cpu {
         //Internal RAM
         cpu_ram: Vec<u8>,
 
-        //'oops' cycles and the like
-        extra_cycles: u8,
         instruction_array: Vec<Instruction>,
-        instruction_cycles: Vec<u8>,
 
-        log: File,
+        //Set by the KIL opcodes, only a reset recovers
+        jammed: bool,
+
+        //Last value on the data bus, what open bus reads return
+        data_bus: u8,
+
+        //PAL runs 3.2 PPU cycles per CPU cycle instead of 3
+        pal_timing: bool,
+
+        //Page written to $4014, the DMA starts on the next read cycle
+        oam_dma_page: Option<u8>,
+
+        //Interrupts, the prev_ values are what was polled one cycle earlier
+        irq_sources: u8,
+        irq_pending: bool,
+        prev_irq_pending: bool,
+        nmi_pending: bool,
+        prev_nmi_pending: bool,
+        nmi_previous: bool, //NMI line level at the last poll, for edge detection
+
+        //Trace of every instruction in nestest.log layout, only the nestest harness turns it on
+        log: Option<File>,
         //Writes to some PPU registers are ignored before ~29658 CPU cycles, store here
-        total_cycles: u16
+        total_cycles: u64
+    }
+
+
+    //The whole machine: the CPU's own state followed by the PPU, APU, cartridge and controllers
+    impl Stateful for Mos6502<'_> {
+        fn save(&self, state: &mut StateWriter) {
+            self.acc.save(state);
+            self.ind_x.save(state);
+            self.ind_y.save(state);
+            self.stat.save(state);
+            self.stck_pnt.save(state);
+            self.prg_cnt.save(state);
+            self.cpu_ram.save(state);
+            self.jammed.save(state);
+            self.data_bus.save(state);
+            self.oam_dma_page.save(state);
+            self.irq_sources.save(state);
+            self.irq_pending.save(state);
+            self.prev_irq_pending.save(state);
+            self.nmi_pending.save(state);
+            self.prev_nmi_pending.save(state);
+            self.nmi_previous.save(state);
+            self.total_cycles.save(state);
+
+            self.ppu.save(state);
+            self.apu.save(state);
+            self.cart.borrow().save(state);
+            for controller in self.controllers.iter() {
+                controller.save_state(state);
+            }
+        }
+
+        fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+            self.acc.load(state)?;
+            self.ind_x.load(state)?;
+            self.ind_y.load(state)?;
+            self.stat.load(state)?;
+            self.stck_pnt.load(state)?;
+            self.prg_cnt.load(state)?;
+            self.cpu_ram.load(state)?;
+            self.jammed.load(state)?;
+            self.data_bus.load(state)?;
+            self.oam_dma_page.load(state)?;
+            self.irq_sources.load(state)?;
+            self.irq_pending.load(state)?;
+            self.prev_irq_pending.load(state)?;
+            self.nmi_pending.load(state)?;
+            self.prev_nmi_pending.load(state)?;
+            self.nmi_previous.load(state)?;
+            self.total_cycles.load(state)?;
+
+            self.ppu.load(state)?;
+            self.apu.load(state)?;
+            self.cart.borrow_mut().load(state)?;
+            for controller in self.controllers.iter_mut() {
+                controller.load_state(state)?;
+            }
+            Ok(())
+        }
     }
 
 
     impl Mos6502<'_> {
 
-        pub fn new<'a>(cart: &'a cartridge::Cartridge, ppu: &'a mut Ricoh2c02<'a>) -> Mos6502<'a> {
+        pub fn new<'a>(cart: &'a RefCell<cartridge::Cartridge>, ppu: &'a mut Ricoh2c02<'a>) -> Mos6502<'a> {
             let instructions: Vec<Instruction> = vec![
-                Instruction::BRK(AddressingMode::Immediate), Instruction::ORA(AddressingMode::IndirectX), Instruction::NAI, Instruction::NAI, Instruction::NOP(AddressingMode::ZeroPage), Instruction::ORA(AddressingMode::ZeroPage), Instruction::ASL(AddressingMode::ZeroPage), Instruction::NAI, 
-                Instruction::PHP(AddressingMode::Implied), Instruction::ORA(AddressingMode::Immediate), Instruction::ASL(AddressingMode::Accumulator), Instruction::NAI, Instruction::NOP(AddressingMode::Absolute), Instruction::ORA(AddressingMode::Absolute), Instruction::ASL(AddressingMode::Absolute), Instruction::NAI, 
-                Instruction::BPL(AddressingMode::Relative), Instruction::ORA(AddressingMode::IndirectY), Instruction::NAI, Instruction::NAI, Instruction::NOP(AddressingMode::ZeroPageX), Instruction::ORA(AddressingMode::ZeroPageX), Instruction::ASL(AddressingMode::ZeroPageX), Instruction::NAI, 
-                Instruction::CLC(AddressingMode::Implied), Instruction::ORA(AddressingMode::AbsoluteIndexY), Instruction::NOP(AddressingMode::Implied), Instruction::NAI, Instruction::NOP(AddressingMode::AbsoluteIndexX), Instruction::ORA(AddressingMode::AbsoluteIndexX), Instruction::ASL(AddressingMode::AbsoluteIndexX), Instruction::NAI,
-                Instruction::JSR(AddressingMode::Immediate), Instruction::AND(AddressingMode::IndirectX), Instruction::NAI, Instruction::NAI, Instruction::BIT(AddressingMode::ZeroPage), Instruction::AND(AddressingMode::ZeroPage), Instruction::ROL(AddressingMode::ZeroPage), Instruction::NAI, 
-                Instruction::PLP(AddressingMode::Implied), Instruction::AND(AddressingMode::Immediate), Instruction::ROL(AddressingMode::Accumulator), Instruction::NAI, Instruction::BIT(AddressingMode::Absolute), Instruction::AND(AddressingMode::Absolute), Instruction::ROL(AddressingMode::Absolute), Instruction::NAI,
-                Instruction::BMI(AddressingMode::Relative), Instruction::AND(AddressingMode::IndirectY), Instruction::NAI, Instruction::NAI, Instruction::NOP(AddressingMode::ZeroPageX), Instruction::AND(AddressingMode::ZeroPageX), Instruction::ROL(AddressingMode::ZeroPageX), Instruction::NAI,
-                Instruction::SEC(AddressingMode::Implied), Instruction::AND(AddressingMode::AbsoluteIndexY), Instruction::NOP(AddressingMode::Implied), Instruction::NAI, Instruction::NOP(AddressingMode::AbsoluteIndexX), Instruction::AND(AddressingMode::AbsoluteIndexX), Instruction::ROL(AddressingMode::AbsoluteIndexX), Instruction::NAI,
-                Instruction::RTI(AddressingMode::Immediate), Instruction::EOR(AddressingMode::IndirectX), Instruction::NAI, Instruction::NAI, Instruction::NOP(AddressingMode::ZeroPage), Instruction::EOR(AddressingMode::ZeroPage), Instruction::LSR(AddressingMode::ZeroPage), Instruction::NAI,
-                Instruction::PHA(AddressingMode::Implied), Instruction::EOR(AddressingMode::Immediate), Instruction::LSR(AddressingMode::Accumulator), Instruction::NAI, Instruction::JMP(AddressingMode::Absolute), Instruction::EOR(AddressingMode::Absolute), Instruction::LSR(AddressingMode::Absolute), Instruction::NAI, 
-                Instruction::BVC(AddressingMode::Relative), Instruction::EOR(AddressingMode::IndirectY), Instruction::NAI, Instruction::NAI, Instruction::NOP(AddressingMode::ZeroPageX), Instruction::EOR(AddressingMode::ZeroPageX), Instruction::LSR(AddressingMode::ZeroPageX), Instruction::NAI, 
-                Instruction::CLI(AddressingMode::Implied), Instruction::EOR(AddressingMode::AbsoluteIndexY), Instruction::NOP(AddressingMode::Implied), Instruction::NAI, Instruction::NOP(AddressingMode::AbsoluteIndexX), Instruction::EOR(AddressingMode::AbsoluteIndexX), Instruction::LSR(AddressingMode::AbsoluteIndexX), Instruction::NAI,
-                Instruction::RTS(AddressingMode::Immediate), Instruction::ADC(AddressingMode::IndirectX), Instruction::NAI, Instruction::NAI, Instruction::NOP(AddressingMode::ZeroPage), Instruction::ADC(AddressingMode::ZeroPage), Instruction::ROR(AddressingMode::ZeroPage), Instruction::NAI, 
-                Instruction::PLA(AddressingMode::Implied), Instruction::ADC(AddressingMode::Immediate), Instruction::ROR(AddressingMode::Accumulator), Instruction::NAI, Instruction::JMP(AddressingMode::Indirect), Instruction::ADC(AddressingMode::Absolute), Instruction::ROR(AddressingMode::Absolute), Instruction::NAI, 
-                Instruction::BVS(AddressingMode::Relative), Instruction::ADC(AddressingMode::IndirectY), Instruction::NAI, Instruction::NAI, Instruction::NOP(AddressingMode::ZeroPageX), Instruction::ADC(AddressingMode::ZeroPageX), Instruction::ROR(AddressingMode::ZeroPageX), Instruction::NAI, 
-                Instruction::SEI(AddressingMode::Implied), Instruction::ADC(AddressingMode::AbsoluteIndexY), Instruction::NOP(AddressingMode::Implied), Instruction::NAI, Instruction::NOP(AddressingMode::AbsoluteIndexX), Instruction::ADC(AddressingMode::AbsoluteIndexX), Instruction::ROR(AddressingMode::AbsoluteIndexX), Instruction::NAI,
-                Instruction::NOP(AddressingMode::Immediate), Instruction::STA(AddressingMode::IndirectX), Instruction::NOP(AddressingMode::Immediate), Instruction::NAI, Instruction::STY(AddressingMode::ZeroPage), Instruction::STA(AddressingMode::ZeroPage), Instruction::STX(AddressingMode::ZeroPage), Instruction::NAI, 
-                Instruction::DEY(AddressingMode::Implied), Instruction::NOP(AddressingMode::Immediate), Instruction::TXA(AddressingMode::Implied), Instruction::NAI, Instruction::STY(AddressingMode::Absolute), Instruction::STA(AddressingMode::Absolute), Instruction::STX(AddressingMode::Absolute), Instruction::NAI, 
-                Instruction::BCC(AddressingMode::Relative), Instruction::STA(AddressingMode::IndirectY), Instruction::NAI, Instruction::NAI, Instruction::STY(AddressingMode::ZeroPageX), Instruction::STA(AddressingMode::ZeroPageX), Instruction::STX(AddressingMode::ZeroPageY), Instruction::NAI, 
-                Instruction::TYA(AddressingMode::Implied), Instruction::STA(AddressingMode::AbsoluteIndexY), Instruction::TXS(AddressingMode::Implied), Instruction::NAI, Instruction::NAI, Instruction::STA(AddressingMode::AbsoluteIndexX), Instruction::NAI, Instruction::NAI,
-                Instruction::LDY(AddressingMode::Immediate), Instruction::LDA(AddressingMode::IndirectX), Instruction::LDX(AddressingMode::Immediate), Instruction::NAI, Instruction::LDY(AddressingMode::ZeroPage), Instruction::LDA(AddressingMode::ZeroPage), Instruction::LDX(AddressingMode::ZeroPage), Instruction::NAI, 
-                Instruction::TAY(AddressingMode::Implied), Instruction::LDA(AddressingMode::Immediate), Instruction::TAX(AddressingMode::Implied), Instruction::NAI, Instruction::LDY(AddressingMode::Absolute), Instruction::LDA(AddressingMode::Absolute), Instruction::LDX(AddressingMode::Absolute), Instruction::NAI, 
-                Instruction::BCS(AddressingMode::Relative), Instruction::LDA(AddressingMode::IndirectY), Instruction::NAI, Instruction::NAI, Instruction::LDY(AddressingMode::ZeroPageX), Instruction::LDA(AddressingMode::ZeroPageX), Instruction::LDX(AddressingMode::ZeroPageY), Instruction::NAI, 
-                Instruction::CLV(AddressingMode::Implied), Instruction::LDA(AddressingMode::AbsoluteIndexY), Instruction::TSX(AddressingMode::Implied), Instruction::NAI, Instruction::LDY(AddressingMode::AbsoluteIndexX), Instruction::LDA(AddressingMode::AbsoluteIndexX), Instruction::LDX(AddressingMode::AbsoluteIndexY), Instruction::NAI,
-                Instruction::CPY(AddressingMode::Immediate), Instruction::CMP(AddressingMode::IndirectX), Instruction::NOP(AddressingMode::Immediate), Instruction::NAI, Instruction::CPY(AddressingMode::ZeroPage), Instruction::CMP(AddressingMode::ZeroPage), Instruction::DEC(AddressingMode::ZeroPage), Instruction::NAI, 
-                Instruction::INY(AddressingMode::Implied), Instruction::CMP(AddressingMode::Immediate), Instruction::DEX(AddressingMode::Implied), Instruction::NAI, Instruction::CPY(AddressingMode::Absolute), Instruction::CMP(AddressingMode::Absolute), Instruction::DEC(AddressingMode::Absolute), Instruction::NAI, 
-                Instruction::BNE(AddressingMode::Relative), Instruction::CMP(AddressingMode::IndirectY), Instruction::NAI, Instruction::NAI, Instruction::NOP(AddressingMode::ZeroPageX), Instruction::CMP(AddressingMode::ZeroPageX), Instruction::DEC(AddressingMode::ZeroPageX), Instruction::NAI, 
-                Instruction::CLD(AddressingMode::Implied), Instruction::CMP(AddressingMode::AbsoluteIndexY), Instruction::NOP(AddressingMode::Implied), Instruction::NAI, Instruction::NOP(AddressingMode::AbsoluteIndexX), Instruction::CMP(AddressingMode::AbsoluteIndexX), Instruction::DEC(AddressingMode::AbsoluteIndexX), Instruction::NAI,
-                Instruction::CPX(AddressingMode::Immediate), Instruction::SBC(AddressingMode::IndirectX), Instruction::NOP(AddressingMode::Immediate), Instruction::NAI, Instruction::CPX(AddressingMode::ZeroPage), Instruction::SBC(AddressingMode::ZeroPage), Instruction::INC(AddressingMode::ZeroPage), Instruction::NAI, 
-                Instruction::INX(AddressingMode::Implied), Instruction::SBC(AddressingMode::Immediate), Instruction::NOP(AddressingMode::Implied), Instruction::SBC(AddressingMode::Immediate), Instruction::CPX(AddressingMode::Absolute), Instruction::SBC(AddressingMode::Absolute), Instruction::INC(AddressingMode::Absolute), Instruction::NAI, 
-                Instruction::BEQ(AddressingMode::Relative), Instruction::SBC(AddressingMode::IndirectY), Instruction::NAI, Instruction::NAI, Instruction::NOP(AddressingMode::ZeroPageX), Instruction::SBC(AddressingMode::ZeroPageX), Instruction::INC(AddressingMode::ZeroPageX), Instruction::NAI, 
-                Instruction::SED(AddressingMode::Implied), Instruction::SBC(AddressingMode::AbsoluteIndexY), Instruction::NOP(AddressingMode::Implied), Instruction::NAI, Instruction::NOP(AddressingMode::AbsoluteIndexX), Instruction::SBC(AddressingMode::AbsoluteIndexX), Instruction::INC(AddressingMode::AbsoluteIndexX), Instruction::NAI,
+                Instruction::BRK(AddressingMode::Implied), Instruction::ORA(AddressingMode::IndirectX), Instruction::KIL(AddressingMode::Implied), Instruction::SLO(AddressingMode::IndirectX), Instruction::NOP(AddressingMode::ZeroPage), Instruction::ORA(AddressingMode::ZeroPage), Instruction::ASL(AddressingMode::ZeroPage), Instruction::SLO(AddressingMode::ZeroPage),
+                Instruction::PHP(AddressingMode::Implied), Instruction::ORA(AddressingMode::Immediate), Instruction::ASL(AddressingMode::Accumulator), Instruction::ANC(AddressingMode::Immediate), Instruction::NOP(AddressingMode::Absolute), Instruction::ORA(AddressingMode::Absolute), Instruction::ASL(AddressingMode::Absolute), Instruction::SLO(AddressingMode::Absolute),
+                Instruction::BPL(AddressingMode::Relative), Instruction::ORA(AddressingMode::IndirectY), Instruction::KIL(AddressingMode::Implied), Instruction::SLO(AddressingMode::IndirectY), Instruction::NOP(AddressingMode::ZeroPageX), Instruction::ORA(AddressingMode::ZeroPageX), Instruction::ASL(AddressingMode::ZeroPageX), Instruction::SLO(AddressingMode::ZeroPageX),
+                Instruction::CLC(AddressingMode::Implied), Instruction::ORA(AddressingMode::AbsoluteIndexY), Instruction::NOP(AddressingMode::Implied), Instruction::SLO(AddressingMode::AbsoluteIndexY), Instruction::NOP(AddressingMode::AbsoluteIndexX), Instruction::ORA(AddressingMode::AbsoluteIndexX), Instruction::ASL(AddressingMode::AbsoluteIndexX), Instruction::SLO(AddressingMode::AbsoluteIndexX),
+                Instruction::JSR(AddressingMode::Absolute), Instruction::AND(AddressingMode::IndirectX), Instruction::KIL(AddressingMode::Implied), Instruction::RLA(AddressingMode::IndirectX), Instruction::BIT(AddressingMode::ZeroPage), Instruction::AND(AddressingMode::ZeroPage), Instruction::ROL(AddressingMode::ZeroPage), Instruction::RLA(AddressingMode::ZeroPage),
+                Instruction::PLP(AddressingMode::Implied), Instruction::AND(AddressingMode::Immediate), Instruction::ROL(AddressingMode::Accumulator), Instruction::ANC(AddressingMode::Immediate), Instruction::BIT(AddressingMode::Absolute), Instruction::AND(AddressingMode::Absolute), Instruction::ROL(AddressingMode::Absolute), Instruction::RLA(AddressingMode::Absolute),
+                Instruction::BMI(AddressingMode::Relative), Instruction::AND(AddressingMode::IndirectY), Instruction::KIL(AddressingMode::Implied), Instruction::RLA(AddressingMode::IndirectY), Instruction::NOP(AddressingMode::ZeroPageX), Instruction::AND(AddressingMode::ZeroPageX), Instruction::ROL(AddressingMode::ZeroPageX), Instruction::RLA(AddressingMode::ZeroPageX),
+                Instruction::SEC(AddressingMode::Implied), Instruction::AND(AddressingMode::AbsoluteIndexY), Instruction::NOP(AddressingMode::Implied), Instruction::RLA(AddressingMode::AbsoluteIndexY), Instruction::NOP(AddressingMode::AbsoluteIndexX), Instruction::AND(AddressingMode::AbsoluteIndexX), Instruction::ROL(AddressingMode::AbsoluteIndexX), Instruction::RLA(AddressingMode::AbsoluteIndexX),
+                Instruction::RTI(AddressingMode::Implied), Instruction::EOR(AddressingMode::IndirectX), Instruction::KIL(AddressingMode::Implied), Instruction::SRE(AddressingMode::IndirectX), Instruction::NOP(AddressingMode::ZeroPage), Instruction::EOR(AddressingMode::ZeroPage), Instruction::LSR(AddressingMode::ZeroPage), Instruction::SRE(AddressingMode::ZeroPage),
+                Instruction::PHA(AddressingMode::Implied), Instruction::EOR(AddressingMode::Immediate), Instruction::LSR(AddressingMode::Accumulator), Instruction::ALR(AddressingMode::Immediate), Instruction::JMP(AddressingMode::Absolute), Instruction::EOR(AddressingMode::Absolute), Instruction::LSR(AddressingMode::Absolute), Instruction::SRE(AddressingMode::Absolute),
+                Instruction::BVC(AddressingMode::Relative), Instruction::EOR(AddressingMode::IndirectY), Instruction::KIL(AddressingMode::Implied), Instruction::SRE(AddressingMode::IndirectY), Instruction::NOP(AddressingMode::ZeroPageX), Instruction::EOR(AddressingMode::ZeroPageX), Instruction::LSR(AddressingMode::ZeroPageX), Instruction::SRE(AddressingMode::ZeroPageX),
+                Instruction::CLI(AddressingMode::Implied), Instruction::EOR(AddressingMode::AbsoluteIndexY), Instruction::NOP(AddressingMode::Implied), Instruction::SRE(AddressingMode::AbsoluteIndexY), Instruction::NOP(AddressingMode::AbsoluteIndexX), Instruction::EOR(AddressingMode::AbsoluteIndexX), Instruction::LSR(AddressingMode::AbsoluteIndexX), Instruction::SRE(AddressingMode::AbsoluteIndexX),
+                Instruction::RTS(AddressingMode::Implied), Instruction::ADC(AddressingMode::IndirectX), Instruction::KIL(AddressingMode::Implied), Instruction::RRA(AddressingMode::IndirectX), Instruction::NOP(AddressingMode::ZeroPage), Instruction::ADC(AddressingMode::ZeroPage), Instruction::ROR(AddressingMode::ZeroPage), Instruction::RRA(AddressingMode::ZeroPage),
+                Instruction::PLA(AddressingMode::Implied), Instruction::ADC(AddressingMode::Immediate), Instruction::ROR(AddressingMode::Accumulator), Instruction::ARR(AddressingMode::Immediate), Instruction::JMP(AddressingMode::Indirect), Instruction::ADC(AddressingMode::Absolute), Instruction::ROR(AddressingMode::Absolute), Instruction::RRA(AddressingMode::Absolute),
+                Instruction::BVS(AddressingMode::Relative), Instruction::ADC(AddressingMode::IndirectY), Instruction::KIL(AddressingMode::Implied), Instruction::RRA(AddressingMode::IndirectY), Instruction::NOP(AddressingMode::ZeroPageX), Instruction::ADC(AddressingMode::ZeroPageX), Instruction::ROR(AddressingMode::ZeroPageX), Instruction::RRA(AddressingMode::ZeroPageX),
+                Instruction::SEI(AddressingMode::Implied), Instruction::ADC(AddressingMode::AbsoluteIndexY), Instruction::NOP(AddressingMode::Implied), Instruction::RRA(AddressingMode::AbsoluteIndexY), Instruction::NOP(AddressingMode::AbsoluteIndexX), Instruction::ADC(AddressingMode::AbsoluteIndexX), Instruction::ROR(AddressingMode::AbsoluteIndexX), Instruction::RRA(AddressingMode::AbsoluteIndexX),
+                Instruction::NOP(AddressingMode::Immediate), Instruction::STA(AddressingMode::IndirectX), Instruction::NOP(AddressingMode::Immediate), Instruction::SAX(AddressingMode::IndirectX), Instruction::STY(AddressingMode::ZeroPage), Instruction::STA(AddressingMode::ZeroPage), Instruction::STX(AddressingMode::ZeroPage), Instruction::SAX(AddressingMode::ZeroPage),
+                Instruction::DEY(AddressingMode::Implied), Instruction::NOP(AddressingMode::Immediate), Instruction::TXA(AddressingMode::Implied), Instruction::XAA(AddressingMode::Immediate), Instruction::STY(AddressingMode::Absolute), Instruction::STA(AddressingMode::Absolute), Instruction::STX(AddressingMode::Absolute), Instruction::SAX(AddressingMode::Absolute),
+                Instruction::BCC(AddressingMode::Relative), Instruction::STA(AddressingMode::IndirectY), Instruction::KIL(AddressingMode::Implied), Instruction::SHA(AddressingMode::IndirectY), Instruction::STY(AddressingMode::ZeroPageX), Instruction::STA(AddressingMode::ZeroPageX), Instruction::STX(AddressingMode::ZeroPageY), Instruction::SAX(AddressingMode::ZeroPageY),
+                Instruction::TYA(AddressingMode::Implied), Instruction::STA(AddressingMode::AbsoluteIndexY), Instruction::TXS(AddressingMode::Implied), Instruction::TAS(AddressingMode::AbsoluteIndexY), Instruction::SHY(AddressingMode::AbsoluteIndexX), Instruction::STA(AddressingMode::AbsoluteIndexX), Instruction::SHX(AddressingMode::AbsoluteIndexY), Instruction::SHA(AddressingMode::AbsoluteIndexY),
+                Instruction::LDY(AddressingMode::Immediate), Instruction::LDA(AddressingMode::IndirectX), Instruction::LDX(AddressingMode::Immediate), Instruction::LAX(AddressingMode::IndirectX), Instruction::LDY(AddressingMode::ZeroPage), Instruction::LDA(AddressingMode::ZeroPage), Instruction::LDX(AddressingMode::ZeroPage), Instruction::LAX(AddressingMode::ZeroPage),
+                Instruction::TAY(AddressingMode::Implied), Instruction::LDA(AddressingMode::Immediate), Instruction::TAX(AddressingMode::Implied), Instruction::LAX(AddressingMode::Immediate), Instruction::LDY(AddressingMode::Absolute), Instruction::LDA(AddressingMode::Absolute), Instruction::LDX(AddressingMode::Absolute), Instruction::LAX(AddressingMode::Absolute),
+                Instruction::BCS(AddressingMode::Relative), Instruction::LDA(AddressingMode::IndirectY), Instruction::KIL(AddressingMode::Implied), Instruction::LAX(AddressingMode::IndirectY), Instruction::LDY(AddressingMode::ZeroPageX), Instruction::LDA(AddressingMode::ZeroPageX), Instruction::LDX(AddressingMode::ZeroPageY), Instruction::LAX(AddressingMode::ZeroPageY),
+                Instruction::CLV(AddressingMode::Implied), Instruction::LDA(AddressingMode::AbsoluteIndexY), Instruction::TSX(AddressingMode::Implied), Instruction::LAS(AddressingMode::AbsoluteIndexY), Instruction::LDY(AddressingMode::AbsoluteIndexX), Instruction::LDA(AddressingMode::AbsoluteIndexX), Instruction::LDX(AddressingMode::AbsoluteIndexY), Instruction::LAX(AddressingMode::AbsoluteIndexY),
+                Instruction::CPY(AddressingMode::Immediate), Instruction::CMP(AddressingMode::IndirectX), Instruction::NOP(AddressingMode::Immediate), Instruction::DCP(AddressingMode::IndirectX), Instruction::CPY(AddressingMode::ZeroPage), Instruction::CMP(AddressingMode::ZeroPage), Instruction::DEC(AddressingMode::ZeroPage), Instruction::DCP(AddressingMode::ZeroPage),
+                Instruction::INY(AddressingMode::Implied), Instruction::CMP(AddressingMode::Immediate), Instruction::DEX(AddressingMode::Implied), Instruction::AXS(AddressingMode::Immediate), Instruction::CPY(AddressingMode::Absolute), Instruction::CMP(AddressingMode::Absolute), Instruction::DEC(AddressingMode::Absolute), Instruction::DCP(AddressingMode::Absolute),
+                Instruction::BNE(AddressingMode::Relative), Instruction::CMP(AddressingMode::IndirectY), Instruction::KIL(AddressingMode::Implied), Instruction::DCP(AddressingMode::IndirectY), Instruction::NOP(AddressingMode::ZeroPageX), Instruction::CMP(AddressingMode::ZeroPageX), Instruction::DEC(AddressingMode::ZeroPageX), Instruction::DCP(AddressingMode::ZeroPageX),
+                Instruction::CLD(AddressingMode::Implied), Instruction::CMP(AddressingMode::AbsoluteIndexY), Instruction::NOP(AddressingMode::Implied), Instruction::DCP(AddressingMode::AbsoluteIndexY), Instruction::NOP(AddressingMode::AbsoluteIndexX), Instruction::CMP(AddressingMode::AbsoluteIndexX), Instruction::DEC(AddressingMode::AbsoluteIndexX), Instruction::DCP(AddressingMode::AbsoluteIndexX),
+                Instruction::CPX(AddressingMode::Immediate), Instruction::SBC(AddressingMode::IndirectX), Instruction::NOP(AddressingMode::Immediate), Instruction::ISC(AddressingMode::IndirectX), Instruction::CPX(AddressingMode::ZeroPage), Instruction::SBC(AddressingMode::ZeroPage), Instruction::INC(AddressingMode::ZeroPage), Instruction::ISC(AddressingMode::ZeroPage),
+                Instruction::INX(AddressingMode::Implied), Instruction::SBC(AddressingMode::Immediate), Instruction::NOP(AddressingMode::Implied), Instruction::SBC(AddressingMode::Immediate), Instruction::CPX(AddressingMode::Absolute), Instruction::SBC(AddressingMode::Absolute), Instruction::INC(AddressingMode::Absolute), Instruction::ISC(AddressingMode::Absolute),
+                Instruction::BEQ(AddressingMode::Relative), Instruction::SBC(AddressingMode::IndirectY), Instruction::KIL(AddressingMode::Implied), Instruction::ISC(AddressingMode::IndirectY), Instruction::NOP(AddressingMode::ZeroPageX), Instruction::SBC(AddressingMode::ZeroPageX), Instruction::INC(AddressingMode::ZeroPageX), Instruction::ISC(AddressingMode::ZeroPageX),
+                Instruction::SED(AddressingMode::Implied), Instruction::SBC(AddressingMode::AbsoluteIndexY), Instruction::NOP(AddressingMode::Implied), Instruction::ISC(AddressingMode::AbsoluteIndexY), Instruction::NOP(AddressingMode::AbsoluteIndexX), Instruction::SBC(AddressingMode::AbsoluteIndexX), Instruction::INC(AddressingMode::AbsoluteIndexX), Instruction::ISC(AddressingMode::AbsoluteIndexX),
             ];
 
-            let cycles: Vec<u8> = vec![
-                7, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6,
-                2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
-                6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6,
-                2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
-                6, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6,
-                2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
-                6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6,
-                2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
-                2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
-                2, 6, 2, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5,
-                2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
-                2, 5, 2, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4,
-                2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
-                2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
-                2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
-                2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7
-            ];
+            let region = cart.borrow().header.region;
+            let mut apu = Ricoh2a03::new();
+            apu.set_region(region);
 
             Mos6502 { 
-                cart: cart, 
-                ppu: ppu,
+                cart, 
+                ppu,
+                apu,
+                controllers: [Box::new(Joypad::new()), Box::new(Joypad::new())],
                 acc: 0, 
                 ind_x: 0, 
                 ind_y: 0, 
@@ -134,22 +257,102 @@ pub mod cpu {
                 stck_pnt: 0xFD, 
                 prg_cnt: 0xFFFC, 
                 cpu_ram: vec![0; 2048], 
-                extra_cycles: 0, 
                 instruction_array: instructions,
-                instruction_cycles: cycles,
-                log: File::create("CPU_LOG.txt").unwrap(),
-                total_cycles: 7
+                jammed: false,
+                pal_timing: region == Region::Pal,
+                oam_dma_page: None,
+                irq_sources: 0,
+                irq_pending: false,
+                prev_irq_pending: false,
+                nmi_pending: false,
+                prev_nmi_pending: false,
+                nmi_previous: false,
+                data_bus: 0,
+                log: None,
+                total_cycles: 0
             }
         }
 
 
         pub fn reset(&mut self, power_up: bool) {
-            self.prg_cnt = ((self.cart.cpu_read(0xFFFD) as u16) << 8) | (self.cart.cpu_read(0xFFFC) as u16);         
+            //The reset sequence takes 7 cycles like any other interrupt, but the three pushes are turned into reads
+            self.jammed = false;
+            self.irq_pending = false;
+            self.prev_irq_pending = false;
+            self.nmi_pending = false;
+            self.prev_nmi_pending = false;
+            for _ in 0..5 {
+                self.tick();
+            }
+            self.prg_cnt = self.read(0xFFFC) as u16 | (self.read(0xFFFD) as u16) << 8;
             //APU registers, they all start as 00, need to check reset vs power up
             if power_up { return; }
             self.ppu.reset();
-            self.stat = self.stat | 0x04;
-            self.stck_pnt = self.stck_pnt - 3;
+            self.apu.reset();
+            self.stat |= 0x04;
+            self.stck_pnt = self.stck_pnt.wrapping_sub(3);
+        }
+
+
+        //Used by test harnesses to start execution somewhere other than the reset vector
+        pub fn set_program_counter(&mut self, addr: u16) {
+            self.prg_cnt = addr;
+        }
+
+
+        //Start or stop writing the instruction trace, a failed write stops it
+        pub fn set_log(&mut self, log: Option<File>) {
+            self.log = log;
+        }
+
+
+        /*
+            Reads memory for traces and test reports. PPU and APU registers read as 0, as does $4020-$5FFF since
+            mapper registers live there (reading MMC5's $5204 acknowledges its IRQ). $6000-$FFFF still goes
+            through the mapper's cpu_read, none of the mappers change state on reads in that range.
+        */
+        pub fn peek(&self, addr: u16) -> u8 {
+            match addr {
+                0x0000..=0x1FFF => self.cpu_ram[(addr & 0x07FF) as usize],
+                0x6000..=0xFFFF => self.cart.borrow_mut().cpu_read(addr),
+                _ => 0
+            }
+        }
+
+
+        /*
+            State before the next instruction in the same layout as nestest.log, minus the disassembly:
+            C000  4C F5 C5                                  A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+        */
+        pub fn trace(&self) -> String {
+            let opcode = self.peek(self.prg_cnt);
+            let bytes: Vec<String> = (0..self.instruction_array[opcode as usize].length())
+                .map(|i| format!("{:02X}", self.peek(self.prg_cnt.wrapping_add(i))))
+                .collect();
+
+            format!("{:04X}  {:<8}{:32}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+                self.prg_cnt, bytes.join(" "), "", self.acc, self.ind_x, self.ind_y, self.stat, self.stck_pnt,
+                self.ppu.scanline(), self.ppu.dot(), self.total_cycles)
+        }
+
+
+        //Assert or release the IRQ line on behalf of one source
+        pub fn set_irq(&mut self, source: IrqSource, asserted: bool) {
+            if asserted {
+                self.irq_sources |= source as u8;
+            } else {
+                self.irq_sources &= !(source as u8);
+            }
+        }
+
+
+        //The NMI input is edge sensitive, a pending NMI is latched when the PPU's output goes from low to high
+        fn poll_nmi(&mut self) {
+            let line = self.ppu.nmi_line();
+            if line && !self.nmi_previous {
+                self.nmi_pending = true;
+            }
+            self.nmi_previous = line;
         }
 
 
@@ -165,16 +368,16 @@ pub mod cpu {
                 0x4000..=0x4014 => 0,
 
                 //APU Status
-                0x4015 => 0,
+                0x4015 => self.apu.read_status(),
 
-                //Joystick one data
-                0x4016 => 0,
+                //Joystick one data, the upper 3 bits aren't driven and keep the last value on the bus
+                0x4016 => (self.data_bus & 0xE0) | (self.controllers[0].read() & 0x1F),
 
                 //Joystick two data
-                0x4017 => 0,
+                0x4017 => (self.data_bus & 0xE0) | (self.controllers[1].read() & 0x1F),
 
                 //$4020–$FFFF Cartridge space: PRG ROM, PRG RAM, and mapper registers 
-                0x4020..=0xFFFF => self.cart.cpu_read(addr),
+                0x4020..=0xFFFF => self.cart.borrow_mut().cpu_read(addr),
 
                 _ => 0
             }
@@ -189,35 +392,169 @@ pub mod cpu {
                 0x2000..=0x3FFF => {
                     //PPU registers - 0x2000-0x2007 mirrored every 8 bytes
                     self.ppu.register_write(((addr & 0x2007) - 0x2000) as u8, value, self.total_cycles);
+                    self.cart.borrow_mut().ppu_register_write(addr, value);
                 },
                 0x4000..=0x4013 | 0x4015 => {
                     //APU Registers
+                    self.apu.register_write(addr, value);
                 },
                 0x4014 => {
                     //OAM DMA
+                    self.oam_dma_page = Some(value);
                 },              
                 0x4016 => {
-                    //Joystick strobe
+                    //Joystick strobe, shared by both ports
+                    for port in self.controllers.iter_mut() {
+                        port.write(value & 0x01 != 0);
+                    }
                 },              
                 0x4017 => {
                     //Frame counter control
+                    self.apu.register_write(addr, value);
                 },              
                 0x4020..=0xFFFF => {
-                    self.cart.cpu_write(addr, value);
+                    self.cart.borrow_mut().cpu_write(addr, value);
                 },
                 _ => ()
             }
         }
 
 
-        pub fn execute_instruction(&mut self) -> u8 {
-            //Fetch the opcode and the next byte
-            let opcode = self.fetch_from_address(self.prg_cnt);
+        /*
+            Every bus access takes exactly one CPU cycle. read and write advance the rest of the system by
+            that cycle before touching the bus, so everything an instruction does, dummy accesses included,
+            is seen by the PPU at the right time. fetch_from_address and writeback are the bare bus.
+        */
+        fn tick(&mut self) {
+            self.total_cycles += 1;
+            //16 PPU cycles every 5 CPU cycles on PAL
+            let extra = self.pal_timing && self.total_cycles.is_multiple_of(5);
+            self.ppu.generate_signal(3 + extra as u16);
+            let expansion = self.cart.borrow().audio_output();
+            self.apu.set_expansion_output(expansion);
+            self.apu.clock();
+            self.set_irq(IrqSource::FrameCounter, self.apu.frame_irq());
+            self.set_irq(IrqSource::Dmc, self.apu.dmc_irq());
+            self.cart.borrow_mut().cpu_clock();
+            self.set_irq(IrqSource::Mapper, self.cart.borrow().irq());
+
+            //Interrupt lines are polled every cycle, but the decision to service one at the end of
+            //an instruction is made from what was polled at the end of its second to last cycle
+            self.prev_nmi_pending = self.nmi_pending;
+            self.poll_nmi();
+            self.prev_irq_pending = self.irq_pending;
+            self.irq_pending = self.irq_sources != 0 && self.stat & 0x04 == 0;
+        }
 
-            writeln!(self.log, "{:04X} {:02X}\tA:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}\tCYC:{}",
-            self.prg_cnt, opcode, self.acc, self.ind_x, self.ind_y, self.stat, self.stck_pnt, self.total_cycles).unwrap();
-            self.extra_cycles = 0;
-            self.prg_cnt += 1;
+
+        fn read(&mut self, addr: u16) -> u8 {
+            //DMA can only halt the CPU on a read cycle
+            if let Some(page) = self.oam_dma_page.take() {
+                self.oam_dma(page, addr);
+            }
+            if self.apu.dmc_dma_address().is_some() {
+                self.dmc_dma(addr);
+            }
+            self.tick();
+            self.data_bus = self.fetch_from_address(addr);
+            self.data_bus
+        }
+
+
+        fn write(&mut self, addr: u16, value: u8) {
+            self.tick();
+            self.data_bus = value;
+            self.writeback(addr, value);
+        }
+
+
+        /*
+            Copies $XX00-$XXFF to OAM starting at the current OAMADDR.
+            The CPU is halted for one cycle, one more if that was a write (odd) cycle so the
+            transfer starts on a read cycle, then 256 read/write pairs: 513 or 514 cycles in total.
+            A DMC fetch during the transfer takes the place of one OAM read and costs a realignment cycle.
+        */
+        fn oam_dma(&mut self, page: u8, cpu_addr: u16) {
+            self.tick();
+            self.fetch_from_address(cpu_addr);
+            if self.total_cycles % 2 == 1 {
+                self.tick();
+            }
+            for low in 0..=0xFF {
+                if self.apu.dmc_dma_address().is_some() {
+                    self.dmc_dma_read();
+                    self.tick();
+                }
+                self.tick();
+                let value = self.fetch_from_address((page as u16) << 8 | low);
+                self.tick();
+                self.ppu.oam_dma_write(value);
+            }
+        }
+
+
+        /*
+            DMC sample fetch: halt cycle, dummy cycle, an alignment cycle if needed, then the read, 3 or 4 cycles.
+            The halted CPU keeps repeating the read it was about to make, which is what causes the extra
+            $4016/$4017 reads that corrupt controller input when a DMC fetch lands on one.
+        */
+        fn dmc_dma(&mut self, cpu_addr: u16) {
+            self.tick();
+            self.fetch_from_address(cpu_addr);
+            self.tick();
+            self.fetch_from_address(cpu_addr);
+            if self.total_cycles % 2 == 1 {
+                self.tick();
+                self.fetch_from_address(cpu_addr);
+            }
+            self.dmc_dma_read();
+        }
+
+
+        fn dmc_dma_read(&mut self) {
+            if let Some(addr) = self.apu.dmc_dma_address() {
+                self.tick();
+                let value = self.cart.borrow_mut().cpu_read(addr);
+                self.apu.dmc_dma_complete(value);
+            }
+        }
+
+
+        //Read the byte at the program counter and increment it
+        fn read_pc(&mut self) -> u8 {
+            let value = self.read(self.prg_cnt);
+            self.prg_cnt = self.prg_cnt.wrapping_add(1);
+            value
+        }
+
+
+        //Returns the number of cycles taken including DMA stalls, 0 if the CPU has been halted by a KIL opcode
+        pub fn execute_instruction(&mut self) -> u16 {
+            if self.jammed { return 0; }
+            let start_cycle = self.total_cycles;
+
+            //Interrupts are serviced between instructions, the sequence starts with two reads of the PC that are thrown away
+            if self.prev_nmi_pending || self.prev_irq_pending {
+                self.read(self.prg_cnt);
+                self.read(self.prg_cnt);
+                self.interrupt(false);
+                return (self.total_cycles - start_cycle) as u16;
+            }
+
+            if self.log.is_some() {
+                let line = self.trace();
+                if let Some(log) = &mut self.log {
+                    if writeln!(log, "{}", line).is_err() {
+                        self.log = None;
+                    }
+                }
+            }
+
+            //Fetch the opcode, single byte instructions always read the next byte and throw it away
+            let opcode = self.read_pc();
+            if self.instruction_array[opcode as usize].length() == 1 {
+                self.read(self.prg_cnt);
+            }
 
             match self.instruction_array[opcode as usize] {
                 Instruction::ADC(mode) => self.adc(&mode),
@@ -250,10 +587,10 @@ pub mod cpu {
                 Instruction::LDX(mode) => self.ldx(&mode),
                 Instruction::LDY(mode) => self.ldy(&mode),
                 Instruction::LSR(mode) => self.lsr(&mode),
-                Instruction::NOP(_mode) => (),
+                Instruction::NOP(mode) => self.nop(&mode),
                 Instruction::ORA(mode) => self.ora(&mode),
                 Instruction::PHA(_mode) => self.push(self.acc),
-                Instruction::PHP(_mode) => self.push(self.stat),
+                Instruction::PHP(_mode) => self.push(self.stat | 0x30),
                 Instruction::PLA(_mode) => self.pla(),
                 Instruction::PLP(_mode) => self.plp(),
                 Instruction::ROL(mode) => self.rol(&mode),
@@ -273,83 +610,130 @@ pub mod cpu {
                 Instruction::TXA(_mode) => self.txa(),
                 Instruction::TXS(_mode) => self.txs(),
                 Instruction::TYA(_mode) => self.tya(),
-                Instruction::NAI => println!("Unofficial/Unassigned opcode!")
+                Instruction::ALR(mode) => self.alr(&mode),
+                Instruction::ANC(mode) => self.anc(&mode),
+                Instruction::ARR(mode) => self.arr(&mode),
+                Instruction::AXS(mode) => self.axs(&mode),
+                Instruction::DCP(mode) => self.dcp(&mode),
+                Instruction::ISC(mode) => self.isc(&mode),
+                Instruction::KIL(_mode) => {
+                    self.jammed = true;
+                    return 0;
+                },
+                Instruction::LAS(mode) => self.las(&mode),
+                Instruction::LAX(mode) => self.lax(&mode),
+                Instruction::RLA(mode) => self.rla(&mode),
+                Instruction::RRA(mode) => self.rra(&mode),
+                Instruction::SAX(mode) => self.store(self.acc & self.ind_x, &mode),
+                Instruction::SHA(mode) => self.unstable_store(self.acc & self.ind_x, &mode),
+                Instruction::SHX(mode) => self.unstable_store(self.ind_x, &mode),
+                Instruction::SHY(mode) => self.unstable_store(self.ind_y, &mode),
+                Instruction::SLO(mode) => self.slo(&mode),
+                Instruction::SRE(mode) => self.sre(&mode),
+                Instruction::TAS(mode) => {
+                    self.stck_pnt = self.acc & self.ind_x;
+                    self.unstable_store(self.stck_pnt, &mode);
+                },
+                Instruction::XAA(mode) => self.xaa(&mode),
             };
-            self.total_cycles += (self.instruction_cycles[opcode as usize] + self.extra_cycles) as u16;
-            self.instruction_cycles[opcode as usize] + self.extra_cycles
+
+            //CLI, SEI and PLP change the I flag on their last cycle, after the IRQ line was polled,
+            //so their effect on IRQs is delayed by one instruction without any special handling
+            (self.total_cycles - start_cycle) as u16
         }
 
 
         //Returns a tuple where 1st element is data to operate on, 2nd is the writeback address
         fn fetch_instruction_data(&mut self, mode: &AddressingMode) -> (u8, u16) {
-            let data: u8 = self.fetch_from_address(self.prg_cnt);
-            if let AddressingMode::Accumulator = mode {self.prg_cnt -= 1;} //If addressing mode is accumulator, we need to cancel out the increment on the next line
-            self.prg_cnt += 1;
             match mode {
                 AddressingMode::Accumulator => (self.acc, 0),
-                AddressingMode::Immediate => (data, 0),
-                AddressingMode::ZeroPage => (self.cpu_ram[data as usize], data as u16),
-                AddressingMode::Absolute => {
-                    let addr = (self.fetch_from_address(self.prg_cnt) as u16) << 8 | data as u16;
-                    self.prg_cnt += 1;
-                    (self.fetch_from_address(addr), addr) 
+                AddressingMode::Immediate => (self.read_pc(), 0),
+                _ => {
+                    let addr = self.operand_address(mode, false);
+                    (self.read(addr), addr)
+                }
+            }
+        }
+
+
+        //Read-modify-write instructions write the unmodified value back before writing the result
+        fn fetch_modify_data(&mut self, mode: &AddressingMode) -> (u8, u16) {
+            if let AddressingMode::Accumulator = mode {
+                return (self.acc, 0);
+            }
+            let addr = self.operand_address(mode, true);
+            let data = self.read(addr);
+            self.write(addr, data);
+            (data, addr)
+        }
+
+
+        //Resolves the effective address of an operand, performing every read the 6502 makes along the way.
+        //Writes and read-modify-writes always spend the indexed 'oops' cycle, reads only when a page is crossed.
+        fn operand_address(&mut self, mode: &AddressingMode, is_write: bool) -> u16 {
+            match mode {
+                AddressingMode::ZeroPage => self.read_pc() as u16,
+                AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => {
+                    let base = self.read_pc();
+                    self.read(base as u16);
+                    base.wrapping_add(if let AddressingMode::ZeroPageX = mode {self.ind_x} else {self.ind_y}) as u16
                 },
+                AddressingMode::Absolute => self.read_pc() as u16 | (self.read_pc() as u16) << 8,
                 AddressingMode::AbsoluteIndexX => {
-                    let mut addr: u16 = (self.fetch_from_address(self.prg_cnt) as u16) << 8 | data as u16;
-                    self.prg_cnt += 1;
-                    //'oops' cycle, but only for read instructions that cross a page
-                    if let Option::None = data.checked_add(self.ind_x) {
-                        addr = addr.wrapping_add(self.ind_x as u16);
-                        self.extra_cycles += 1;
-                    } else {
-                        addr += self.ind_x as u16;
-                    }
-                    (self.fetch_from_address(addr), addr)
+                    let base = self.read_pc() as u16 | (self.read_pc() as u16) << 8;
+                    self.index_address(base, self.ind_x, is_write)
                 },
                 AddressingMode::AbsoluteIndexY => {
-                    let mut addr: u16 = (self.fetch_from_address(self.prg_cnt) as u16) << 8 | data as u16;
-                    self.prg_cnt += 1;
-                    //'oops' cycle, but only for read instructions that cross a page
-                    if let Option::None = data.checked_add(self.ind_y) {
-                        addr = addr.wrapping_add(self.ind_y as u16);
-                        self.extra_cycles += 1;
-                    } else {
-                        addr += self.ind_y as u16;
-                    }
-                    (self.fetch_from_address(addr), addr)
+                    let base = self.read_pc() as u16 | (self.read_pc() as u16) << 8;
+                    self.index_address(base, self.ind_y, is_write)
                 },
-                AddressingMode::ZeroPageX => (self.cpu_ram[data.wrapping_add(self.ind_x) as usize], data.wrapping_add(self.ind_x) as u16),
-                AddressingMode::ZeroPageY => (self.cpu_ram[data.wrapping_add(self.ind_y) as usize], data.wrapping_add(self.ind_y) as u16),
                 AddressingMode::IndirectX => {
-                    let addr = (self.cpu_ram[data.wrapping_add(self.ind_x).wrapping_add(1) as usize] as u16) << 8 | self.cpu_ram[data.wrapping_add(self.ind_x) as usize] as u16;
-                    (self.fetch_from_address(addr), addr) 
+                    let pointer = self.read_pc();
+                    self.read(pointer as u16);
+                    let pointer = pointer.wrapping_add(self.ind_x);
+                    self.read(pointer as u16) as u16 | (self.read(pointer.wrapping_add(1) as u16) as u16) << 8
                 },
                 AddressingMode::IndirectY => {
-                    let high: u16 = self.cpu_ram[data.wrapping_add(1) as usize] as u16;
-                    let addr: u16 = high << 8 | self.cpu_ram[data as usize] as u16;
-                    if high + self.ind_y as u16 > 255 { self.extra_cycles += 1; }
-                    (self.fetch_from_address(addr.wrapping_add(self.ind_y as u16)), addr)
+                    let pointer = self.read_pc();
+                    let base = self.read(pointer as u16) as u16 | (self.read(pointer.wrapping_add(1) as u16) as u16) << 8;
+                    self.index_address(base, self.ind_y, is_write)
                 },
-                _ => (0,0)
+                _ => 0
+            }
+        }
+
+
+        //The index is added to the low byte first, so the 'oops' cycle is a read from the address before the carry is applied
+        fn index_address(&mut self, base: u16, index: u8, is_write: bool) -> u16 {
+            let addr = base.wrapping_add(index as u16);
+            if is_write || (addr & 0xFF00) != (base & 0xFF00) {
+                self.read((base & 0xFF00) | (addr & 0x00FF));
             }
+            addr
         }
 
 
 
         fn adc(&mut self, mode: &AddressingMode) {
             let data: (u8, u16) = self.fetch_instruction_data(mode);
-            let sum: u8 = self.acc.wrapping_add(data.0).wrapping_add(self.stat & 1);
+            self.add_with_carry(data.0);
+        }
+
+
+        //Shared by ADC, SBC and RRA, SBC adds the one's complement of its operand
+        fn add_with_carry(&mut self, value: u8) {
+            let sum: u16 = self.acc as u16 + value as u16 + (self.stat & 1) as u16;
             self.stat &= 0xBE;
 
-            //Carry
-            if ((self.acc & 0x80) == (data.0 & 0x80)) && ((sum & 0x80) != (self.acc & 0x80)) {
+            //Overflow
+            if ((self.acc & 0x80) == (value & 0x80)) && ((sum as u8 & 0x80) != (self.acc & 0x80)) {
                 self.stat |= 0x40;
             }
-            //Overflow
-            if sum <= self.acc && sum <= data.0 {
+            //Carry
+            if sum > 0xFF {
                 self.stat |= 0x01;
             }
-            self.acc = sum;
+            self.acc = sum as u8;
             self.examine_status(self.acc);          
         }
 
@@ -361,13 +745,13 @@ pub mod cpu {
 
 
         fn asl(&mut self, mode: &AddressingMode) {
-            let mut data = self.fetch_instruction_data(mode);
+            let mut data = self.fetch_modify_data(mode);
             self.stat = (self.stat & 0xFE) | ((data.0 & 0x80) >> 7);
-            data.0 = data.0 << 1;
+            data.0 <<= 1;
             if let AddressingMode::Accumulator = mode {
                 self.acc = data.0;
             } else {
-                self.writeback(data.1, data.0)
+                self.write(data.1, data.0)
             }
             self.examine_status(data.0);
         }
@@ -393,26 +777,22 @@ pub mod cpu {
                 _ => 0
             };
 
-            let op: i8 = self.fetch_from_address(self.prg_cnt) as i8;
-            self.prg_cnt += 1;
+            let op: i8 = self.read_pc() as i8;
 
             if comp == value {
-                
-                if op < 0 {
-                    //negative, sub from pc
-                    if ((self.prg_cnt - op.abs() as u16) & 0x0F00) != (self.prg_cnt & 0x0F00) {
-                        self.extra_cycles += 1;
-                    }
-                    self.prg_cnt -= op.abs() as u16;
-                } else {
-                    //positive, add to pc
-                    if ((self.prg_cnt + op as u16) & 0x0F00) != (self.prg_cnt & 0x0F00) {
-                        self.extra_cycles += 1;
-                    }
-                    self.prg_cnt += op as u16;
+                //A taken branch that doesn't cross a page doesn't poll interrupts on its extra cycle,
+                //an IRQ that first appears there waits until after the next instruction
+                if self.irq_pending && !self.prev_irq_pending {
+                    self.irq_pending = false;
                 }
-                self.extra_cycles += 1;
+                self.read(self.prg_cnt);
 
+                let target = self.prg_cnt.wrapping_add(op as u16);
+                if (target & 0xFF00) != (self.prg_cnt & 0xFF00) {
+                    //The PC high byte is fixed up one cycle late, that cycle reads from the wrong page
+                    self.read((self.prg_cnt & 0xFF00) | (target & 0x00FF));
+                }
+                self.prg_cnt = target;
             }
         }
 
@@ -433,12 +813,22 @@ pub mod cpu {
         */
         fn brk(&mut self) {
             self.prg_cnt += 1;
-            self.cpu_ram[(0x0100 + self.stck_pnt as u16) as usize] = ((self.prg_cnt & 0xFF00) >> 8) as u8;
-            self.cpu_ram[(0x00FF + self.stck_pnt as u16) as usize] = (self.prg_cnt & 0xFF) as u8;
-            self.cpu_ram[(0x00FE + self.stck_pnt as u16) as usize] = self.stat | 0x30;
-            self.stck_pnt -= 3;
-            self.prg_cnt = self.fetch_from_address(0xFFFE) as u16 | (self.fetch_from_address(0xFFFF) as u16) << 8;
-            self.stat |= 0x14;
+            self.interrupt(true);
+        }
+
+
+        /*
+            BRK, NMI and IRQ share the same sequence, only BRK pushes the status with the B flag set.
+            The vector is chosen after the pushes, so an NMI detected by then hijacks a BRK or IRQ and $FFFA is used.
+        */
+        fn interrupt(&mut self, brk: bool) {
+            self.push(((self.prg_cnt & 0xFF00) >> 8) as u8);
+            self.push((self.prg_cnt & 0xFF) as u8);
+            let vector: u16 = if self.nmi_pending {0xFFFA} else {0xFFFE};
+            self.nmi_pending = false;
+            self.push(self.stat | if brk {0x30} else {0x20});
+            self.stat |= 0x04;
+            self.prg_cnt = self.read(vector) as u16 | (self.read(vector + 1) as u16) << 8;
         }
 
         //AND mask with status register
@@ -449,34 +839,34 @@ pub mod cpu {
 
         fn cmp(&mut self, mode: &AddressingMode) {
             let data = self.fetch_instruction_data(mode);
-            self.stat &= 0x7C;
-            if self.acc >= data.0 { self.stat |= 0x01; }
-            if self.acc == data.0 { self.stat |= 0x02; }
-            if (self.acc.wrapping_sub(data.0)) & 0x80 == 0x80 { self.stat |= 0x80 }
+            self.compare(self.acc, data.0);
         }
 
         
         fn cpx(&mut self, mode: &AddressingMode) {
             let data = self.fetch_instruction_data(mode);
-            self.stat &= 0x7C;
-            if self.ind_x >= data.0 { self.stat |= 0x01; }
-            if self.ind_x == data.0 { self.stat |= 0x02; }
-            if (self.ind_x.wrapping_sub(data.0)) & 0x80 == 0x80 { self.stat |= 0x80 }
+            self.compare(self.ind_x, data.0);
         }
 
 
         fn cpy(&mut self, mode: &AddressingMode) {
             let data = self.fetch_instruction_data(mode);
+            self.compare(self.ind_y, data.0);
+        }
+
+
+        //CMP, CPX, CPY and DCP
+        fn compare(&mut self, register: u8, value: u8) {
             self.stat &= 0x7C;
-            if self.ind_y >= data.0 { self.stat |= 0x01; }
-            if self.ind_y == data.0 { self.stat |= 0x02; }
-            if (self.ind_y.wrapping_sub(data.0)) & 0x80 == 0x80 { self.stat |= 0x80 }
+            if register >= value { self.stat |= 0x01; }
+            if register == value { self.stat |= 0x02; }
+            if (register.wrapping_sub(value)) & 0x80 == 0x80 { self.stat |= 0x80 }
         }
 
 
         fn dec(&mut self, mode: &AddressingMode) {
-            let data = self.fetch_instruction_data(mode);
-            self.writeback(data.1, data.0.wrapping_sub(1));
+            let data = self.fetch_modify_data(mode);
+            self.write(data.1, data.0.wrapping_sub(1));
             self.examine_status(data.0.wrapping_sub(1));
         }
 
@@ -501,8 +891,8 @@ pub mod cpu {
 
 
         fn inc(&mut self, mode: &AddressingMode) {
-            let data = self.fetch_instruction_data(mode);
-            self.writeback(data.1, data.0.wrapping_add(1));
+            let data = self.fetch_modify_data(mode);
+            self.write(data.1, data.0.wrapping_add(1));
             self.examine_status(data.0.wrapping_add(1));
         }
 
@@ -519,13 +909,14 @@ pub mod cpu {
 
         //JMP Instruction - Absolute or indirect addressing modes
         fn jmp(&mut self, mode: &AddressingMode) {
+            let target = self.read_pc() as u16 | (self.read(self.prg_cnt) as u16) << 8;
             if let AddressingMode::Absolute = mode {
-                self.prg_cnt = (self.fetch_from_address(self.prg_cnt + 1) as u16) << 8 | self.fetch_from_address(self.prg_cnt) as u16;
+                self.prg_cnt = target;
             } else {
                 //Indirect addressing mode
-                let target = (self.fetch_from_address(self.prg_cnt + 1) as u16) << 8 | self.fetch_from_address(self.prg_cnt) as u16;
                 //If the indirect vector falls on a page boundary, ($xxFF), top byte of final jump destination is fetched from $xx00
-                self.prg_cnt = (self.fetch_from_address(if target & 0xFF == 0xFF { target & 0xFF00} else {target + 1}) as u16) << 8 | self.fetch_from_address(target) as u16;
+                let low = self.read(target) as u16;
+                self.prg_cnt = (self.read(if target & 0xFF == 0xFF { target & 0xFF00} else {target + 1}) as u16) << 8 | low;
             }
         }
 
@@ -541,11 +932,11 @@ pub mod cpu {
                             byte to PCH
         */
         fn jsr(&mut self) {
-            self.cpu_ram[(0x0100 + self.stck_pnt as u16) as usize] = ((1 + self.prg_cnt & 0xFF00) >> 8) as u8;
-            self.cpu_ram[(0x00FF + self.stck_pnt as u16) as usize] = (1 + self.prg_cnt & 0xFF) as u8;
-            self.stck_pnt -= 2;
-
-            self.prg_cnt = (self.fetch_from_address(self.prg_cnt + 1) as u16) << 8 | self.fetch_from_address(self.prg_cnt) as u16;
+            let low = self.read_pc() as u16;
+            self.read(0x0100 + self.stck_pnt as u16);
+            self.push(((self.prg_cnt & 0xFF00) >> 8) as u8);
+            self.push((self.prg_cnt & 0xFF) as u8);
+            self.prg_cnt = (self.read(self.prg_cnt) as u16) << 8 | low;
         }
 
 
@@ -571,12 +962,12 @@ pub mod cpu {
 
 
         fn lsr(&mut self, mode: &AddressingMode) {
-            let data = self.fetch_instruction_data(mode);
+            let data = self.fetch_modify_data(mode);
             let temp = data.0 >> 1;
             if let AddressingMode::Accumulator = mode {
                 self.acc = temp;
             } else {
-                self.writeback(data.1, temp);
+                self.write(data.1, temp);
             }
             self.stat &= 0xFE;
             self.stat |= data.0 & 0x01;
@@ -594,30 +985,37 @@ pub mod cpu {
         //Push instructions all as one function - push register onto stack
         //PHA, PHP
         fn push(&mut self, register: u8) {
-            self.cpu_ram[(0x0100 + self.stck_pnt as u16) as usize] = register;
-            self.stck_pnt -= 1;
+            self.write(0x0100 + self.stck_pnt as u16, register);
+            self.stck_pnt = self.stck_pnt.wrapping_sub(1);
+        }
+
+
+        fn pull(&mut self) -> u8 {
+            self.stck_pnt = self.stck_pnt.wrapping_add(1);
+            self.read(0x0100 + self.stck_pnt as u16)
         }
 
 
         fn pla(&mut self) {
-            self.stck_pnt += 1;
-            self.acc = self.cpu_ram[(0x0100 + self.stck_pnt as u16) as usize];         
+            self.read(0x0100 + self.stck_pnt as u16);
+            self.acc = self.pull();
             self.examine_status(self.acc);
         }
 
         fn plp(&mut self) {
-            self.stck_pnt += 1;
-            self.stat = self.cpu_ram[(0x0100 + self.stck_pnt as u16) as usize];       
+            self.read(0x0100 + self.stck_pnt as u16);
+            //Bits 4 and 5 don't exist in the status register and are ignored when pulled
+            self.stat = (self.pull() & 0xCF) | 0x20;
         }
 
 
         fn rol(&mut self, mode: &AddressingMode) {
-            let data = self.fetch_instruction_data(mode);
+            let data = self.fetch_modify_data(mode);
             let temp = (data.0 << 1) | (self.stat & 0x01);
             if let AddressingMode::Accumulator = mode {
                 self.acc = temp;
             } else {
-                self.writeback(data.1, temp);
+                self.write(data.1, temp);
             }
             self.stat &= 0xFE;
             self.stat |= (data.0 & 0x80) >> 7;
@@ -625,12 +1023,12 @@ pub mod cpu {
         }
 
         fn ror(&mut self, mode: &AddressingMode) {
-            let data = self.fetch_instruction_data(mode);
+            let data = self.fetch_modify_data(mode);
             let temp = (data.0 >> 1) | ((self.stat & 0x01) << 7);
             if let AddressingMode::Accumulator = mode {
                 self.acc = temp;
             } else {
-                self.writeback(data.1, temp);
+                self.write(data.1, temp);
             }
             self.stat &= 0xFE;
             self.stat |= data.0 & 0x01;
@@ -648,9 +1046,10 @@ pub mod cpu {
             6  $0100,S  R  pull PCH from stack
         */
         fn rti(&mut self) {
-            self.stat = self.cpu_ram[(0x0101 + self.stck_pnt as u16) as usize];
-            self.prg_cnt = self.cpu_ram[(0x0102 + self.stck_pnt as u16) as usize] as u16 | (self.cpu_ram[(0x0103 + self.stck_pnt as u16) as usize] as u16) << 8;
-            self.stck_pnt += 3;
+            self.read(0x0100 + self.stck_pnt as u16);
+            self.stat = (self.pull() & 0xCF) | 0x20;
+            self.prg_cnt = self.pull() as u16;
+            self.prg_cnt |= (self.pull() as u16) << 8;
         }
 
         /*
@@ -664,19 +1063,16 @@ pub mod cpu {
             6    PC     R  increment PC
         */
         fn rts(&mut self) {
-            self.prg_cnt = self.cpu_ram[(0x0101 + self.stck_pnt as u16) as usize] as u16 | (self.cpu_ram[(0x0102 + self.stck_pnt as u16) as usize] as u16) << 8;
-            self.stck_pnt += 2;
-            self.prg_cnt += 1;
+            self.read(0x0100 + self.stck_pnt as u16);
+            self.prg_cnt = self.pull() as u16;
+            self.prg_cnt |= (self.pull() as u16) << 8;
+            self.read(self.prg_cnt);
+            self.prg_cnt = self.prg_cnt.wrapping_add(1);
         }
        
         fn sbc(&mut self, mode: &AddressingMode) {
             let data = self.fetch_instruction_data(mode);
-            let temp = (self.acc as u16) + ((data.0 as u16) ^ 0xFF) + (self.stat & 0x01) as u16;
-            self.stat &= 0xBE;
-            self.stat |= if temp & 0xFF00 > 0 {1} else {0};
-            self.stat |= if (temp ^ self.acc as u16) & (temp ^ ((data.0 as u16) ^ 0xFF)) & 0x0080 > 0 {0x40} else {0};
-            self.acc = (temp & 0xFF) as u8;
-            self.examine_status(self.acc);
+            self.add_with_carry(data.0 ^ 0xFF);
         }
 
         //OR the mask with the status register
@@ -686,8 +1082,8 @@ pub mod cpu {
 
         //STA, STX, STY as one function
         fn store(&mut self, reg: u8, mode: &AddressingMode) {
-            let data = self.fetch_instruction_data(mode);
-            self.writeback(data.1, reg);
+            let addr = self.operand_address(mode, true);
+            self.write(addr, reg);
         }
 
 
@@ -726,6 +1122,166 @@ pub mod cpu {
         }
 
 
+        //Unofficial NOPs with an operand still perform the read, including the 'oops' cycle
+        fn nop(&mut self, mode: &AddressingMode) {
+            if let AddressingMode::Implied = mode { return; }
+            self.fetch_instruction_data(mode);
+        }
+
+
+        /*
+            Unofficial opcodes, mostly combinations of two official instructions sharing one addressing mode
+        */
+
+        //AND immediate then LSR accumulator
+        fn alr(&mut self, mode: &AddressingMode) {
+            let data = self.fetch_instruction_data(mode);
+            let temp = self.acc & data.0;
+            self.stat = (self.stat & 0xFE) | (temp & 0x01);
+            self.acc = temp >> 1;
+            self.examine_status(self.acc);
+        }
+
+
+        //AND immediate, carry is copied from the negative flag
+        fn anc(&mut self, mode: &AddressingMode) {
+            let data = self.fetch_instruction_data(mode);
+            self.acc &= data.0;
+            self.examine_status(self.acc);
+            self.stat = (self.stat & 0xFE) | (self.acc >> 7);
+        }
+
+
+        //AND immediate then ROR accumulator, carry is bit 6 of the result and overflow is bit 6 XOR bit 5
+        fn arr(&mut self, mode: &AddressingMode) {
+            let data = self.fetch_instruction_data(mode);
+            self.acc = ((self.acc & data.0) >> 1) | ((self.stat & 0x01) << 7);
+            self.examine_status(self.acc);
+            self.stat &= 0xBE;
+            self.stat |= (self.acc >> 6) & 0x01;
+            self.stat |= (((self.acc >> 6) ^ (self.acc >> 5)) & 0x01) << 6;
+        }
+
+
+        //X = (A AND X) - immediate, without borrow, flags set as in CMP
+        fn axs(&mut self, mode: &AddressingMode) {
+            let data = self.fetch_instruction_data(mode);
+            let temp = self.acc & self.ind_x;
+            self.compare(temp, data.0);
+            self.ind_x = temp.wrapping_sub(data.0);
+        }
+
+
+        //DEC memory then CMP
+        fn dcp(&mut self, mode: &AddressingMode) {
+            let data = self.fetch_modify_data(mode);
+            let temp = data.0.wrapping_sub(1);
+            self.write(data.1, temp);
+            self.compare(self.acc, temp);
+        }
+
+
+        //INC memory then SBC
+        fn isc(&mut self, mode: &AddressingMode) {
+            let data = self.fetch_modify_data(mode);
+            let temp = data.0.wrapping_add(1);
+            self.write(data.1, temp);
+            self.add_with_carry(temp ^ 0xFF);
+        }
+
+
+        //A, X and S are all set to memory AND S
+        fn las(&mut self, mode: &AddressingMode) {
+            let data = self.fetch_instruction_data(mode);
+            self.stck_pnt &= data.0;
+            self.acc = self.stck_pnt;
+            self.ind_x = self.stck_pnt;
+            self.examine_status(self.acc);
+        }
+
+
+        //LDA and LDX at once. The immediate version (sometimes called LXA) is unstable, 
+        //the accumulator is ORed with a chip dependent constant before the AND
+        fn lax(&mut self, mode: &AddressingMode) {
+            let data = self.fetch_instruction_data(mode);
+            self.acc = if let AddressingMode::Immediate = mode {(self.acc | UNSTABLE_MAGIC) & data.0} else {data.0};
+            self.ind_x = self.acc;
+            self.examine_status(self.acc);
+        }
+
+
+        //ROL memory then AND
+        fn rla(&mut self, mode: &AddressingMode) {
+            let data = self.fetch_modify_data(mode);
+            let temp = (data.0 << 1) | (self.stat & 0x01);
+            self.write(data.1, temp);
+            self.stat = (self.stat & 0xFE) | (data.0 >> 7);
+            self.acc &= temp;
+            self.examine_status(self.acc);
+        }
+
+
+        //ROR memory then ADC
+        fn rra(&mut self, mode: &AddressingMode) {
+            let data = self.fetch_modify_data(mode);
+            let temp = (data.0 >> 1) | ((self.stat & 0x01) << 7);
+            self.write(data.1, temp);
+            self.stat = (self.stat & 0xFE) | (data.0 & 0x01);
+            self.add_with_carry(temp);
+        }
+
+
+        //ASL memory then ORA
+        fn slo(&mut self, mode: &AddressingMode) {
+            let data = self.fetch_modify_data(mode);
+            let temp = data.0 << 1;
+            self.write(data.1, temp);
+            self.stat = (self.stat & 0xFE) | (data.0 >> 7);
+            self.acc |= temp;
+            self.examine_status(self.acc);
+        }
+
+
+        //LSR memory then EOR
+        fn sre(&mut self, mode: &AddressingMode) {
+            let data = self.fetch_modify_data(mode);
+            let temp = data.0 >> 1;
+            self.write(data.1, temp);
+            self.stat = (self.stat & 0xFE) | (data.0 & 0x01);
+            self.acc ^= temp;
+            self.examine_status(self.acc);
+        }
+
+
+        //SHA, SHX, SHY and TAS store the register ANDed with the high byte of the base address plus one.
+        //When indexing crosses a page the stored value also replaces the high byte of the target address.
+        fn unstable_store(&mut self, reg: u8, mode: &AddressingMode) {
+            let (base, index) = match mode {
+                AddressingMode::IndirectY => {
+                    let pointer = self.read_pc();
+                    (self.read(pointer as u16) as u16 | (self.read(pointer.wrapping_add(1) as u16) as u16) << 8, self.ind_y)
+                },
+                AddressingMode::AbsoluteIndexX => (self.read_pc() as u16 | (self.read_pc() as u16) << 8, self.ind_x),
+                _ => (self.read_pc() as u16 | (self.read_pc() as u16) << 8, self.ind_y)
+            };
+            let mut addr = self.index_address(base, index, true);
+
+            let value = reg & ((base >> 8) as u8).wrapping_add(1);
+            if addr & 0xFF00 != base & 0xFF00 {
+                addr = (value as u16) << 8 | (addr & 0x00FF);
+            }
+            self.write(addr, value);
+        }
+
+
+        //Unstable, A = (A OR constant) AND X AND immediate
+        fn xaa(&mut self, mode: &AddressingMode) {
+            let data = self.fetch_instruction_data(mode);
+            self.acc = (self.acc | UNSTABLE_MAGIC) & self.ind_x & data.0;
+            self.examine_status(self.acc);
+        }
+
+
         //Checking zero and negative flags
         fn examine_status(&mut self, value: u8) {
             self.stat &= 0x7D;
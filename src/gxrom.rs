@@ -0,0 +1,55 @@
+pub mod gxrom {
+    use crate::cartridge::cartridge::{Mapper, Mapped, Mirroring};
+    use crate::savestate::savestate::stateful;
+
+    //GxROM (mapper 66), bits 4-5 select a 32KB PRG bank and bits 0-1 an 8KB CHR bank, always has bus conflicts
+    pub struct Gxrom {
+        prg_size: usize,
+        chr_size: usize,
+        prg_bank: u8,
+        chr_bank: u8,
+        mirroring: Mirroring
+    }
+
+    stateful!(Gxrom { prg_bank, chr_bank });
+
+    impl Mapper for Gxrom {
+        fn cpu_read(&mut self, addr: u16) -> Mapped {
+            match addr {
+                0x8000..=0xFFFF => Mapped::PrgRom((self.prg_bank as usize * 0x8000 + (addr & 0x7FFF) as usize) % self.prg_size.max(1)),
+                _ => Mapped::None
+            }
+        }
+        fn cpu_write(&mut self, addr: u16, value: u8) -> Mapped {
+            if addr >= 0x8000 {
+                self.prg_bank = (value >> 4) & 0x03;
+                self.chr_bank = value & 0x03;
+            }
+            Mapped::None
+        }
+        fn ppu_read(&mut self, addr: u16) -> Mapped {
+            Mapped::Chr((self.chr_bank as usize * 0x2000 + addr as usize) % self.chr_size)
+        }
+        fn ppu_write(&mut self, addr: u16, _value: u8) -> Mapped {
+            Mapped::Chr((self.chr_bank as usize * 0x2000 + addr as usize) % self.chr_size)
+        }
+        fn mirroring(&self) -> Mirroring {
+            self.mirroring
+        }
+        fn bus_conflicts(&self) -> bool {
+            true
+        }
+    }
+
+    impl Gxrom {
+        pub fn new(prg_size: usize, chr_size: usize, mirroring: Mirroring) -> Gxrom {
+            Gxrom {
+                prg_size,
+                chr_size: chr_size.max(0x2000),
+                prg_bank: 0,
+                chr_bank: 0,
+                mirroring
+            }
+        }
+    }
+}
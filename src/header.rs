@@ -0,0 +1,312 @@
+pub mod header {
+    use crate::cartridge::cartridge::{Mirroring, RomLoadError};
+
+    //CPU/PPU timing the ROM was made for, from NES 2.0 byte 12 or iNES byte 9
+    #[derive(Debug, Copy, Clone, PartialEq)]
+    pub enum Region {
+        Ntsc,
+        Pal,
+        Multi, //Works on both, run as NTSC
+        Dendy,
+    }
+
+    //NES 2.0 byte 7 bits 0-1, with byte 13 for the Vs. System and extended types
+    #[derive(Debug, Copy, Clone, PartialEq)]
+    pub enum ConsoleType {
+        Nes,
+        VsSystem { ppu: u8, hardware: u8 },
+        Playchoice,
+        Extended(u8),
+    }
+
+    /*
+        Everything the 16 byte header says about the cartridge. iNES 1.0 headers leave most of it out,
+        the missing fields get the values an iNES 1.0 board usually has.
+        0-3     "NES" followed by $1A
+        4       PRG ROM size, low byte
+        5       CHR ROM size, low byte
+        6       Mapper low nibble, four-screen, trainer, battery, vertical mirroring
+        7       Mapper middle nibble, NES 2.0 identifier (bits 2-3 = 2), console type
+        8       Submapper, mapper high nibble                           (iNES: PRG RAM size in 8KB units)
+        9       CHR ROM size high nibble, PRG ROM size high nibble      (iNES: bit 0 PAL)
+        10      PRG NVRAM shift count, PRG RAM shift count, 64 << n bytes
+        11      CHR NVRAM shift count, CHR RAM shift count
+        12      Timing region
+        13      Vs. System PPU and hardware type, or extended console type
+        14      Number of miscellaneous ROMs
+        15      Default expansion device
+    */
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct RomHeader {
+        pub nes2: bool,
+        pub mapper: u16,
+        pub submapper: u8,
+        pub prg_rom_size: usize,
+        pub chr_rom_size: usize,
+        pub prg_ram_size: usize,
+        pub prg_nvram_size: usize,
+        pub chr_ram_size: usize,
+        pub chr_nvram_size: usize,
+        pub mirroring: Mirroring,
+        pub battery: bool,
+        pub trainer: bool,
+        pub region: Region,
+        pub console_type: ConsoleType,
+        pub misc_roms: u8,
+        pub expansion_device: u8,
+    }
+
+
+    impl RomHeader {
+
+        pub fn parse(header: &[u8; 16]) -> Result<RomHeader, RomLoadError> {
+            if header[0..4] != [0x4E, 0x45, 0x53, 0x1A] {
+                return Err(RomLoadError::BadMagic);
+            }
+
+            let nes2 = header[7] & 0x0C == 0x08;
+
+            //Byte 6: bit 0 set for vertical mirroring, bit 3 for four-screen VRAM on the cartridge
+            let mirroring = if header[6] & 0x08 != 0 {
+                Mirroring::FourScreen
+            } else if header[6] & 0x01 != 0 {
+                Mirroring::Vertical
+            } else {
+                Mirroring::Horizontal
+            };
+            let battery = header[6] & 0x02 != 0;
+            let trainer = header[6] & 0x04 != 0;
+
+            let console_type = match header[7] & 0x03 {
+                0 => ConsoleType::Nes,
+                1 => ConsoleType::VsSystem { ppu: header[13] & 0x0F, hardware: header[13] >> 4 },
+                2 => ConsoleType::Playchoice,
+                _ if nes2 => ConsoleType::Extended(header[13] & 0x0F),
+                _ => ConsoleType::Nes
+            };
+
+            if !nes2 {
+                //Old dumping tools wrote their name into bytes 7-15, only trust byte 7's mapper nibble when the rest is clear
+                let dirty = header[7] & 0x0C != 0 || header[12..16].iter().any(|&b| b != 0);
+                let mapper_high = if dirty {0} else {header[7] & 0xF0};
+                let prg_ram_size = if dirty || header[8] == 0 {0x2000} else {header[8] as usize * 0x2000};
+                let chr_rom_size = header[5] as usize * 0x2000;
+                return Ok(RomHeader {
+                    nes2,
+                    mapper: (header[6] >> 4 | mapper_high) as u16,
+                    submapper: 0,
+                    prg_rom_size: header[4] as usize * 0x4000,
+                    chr_rom_size,
+                    //Battery boards keep all of their RAM powered
+                    prg_ram_size: if battery {0} else {prg_ram_size},
+                    prg_nvram_size: if battery {prg_ram_size} else {0},
+                    chr_ram_size: if chr_rom_size == 0 {0x2000} else {0},
+                    chr_nvram_size: 0,
+                    mirroring,
+                    battery,
+                    trainer,
+                    region: if !dirty && header[9] & 0x01 != 0 {Region::Pal} else {Region::Ntsc},
+                    console_type: if dirty {ConsoleType::Nes} else {console_type},
+                    misc_roms: 0,
+                    expansion_device: 0,
+                });
+            }
+
+            Ok(RomHeader {
+                nes2,
+                mapper: (header[6] >> 4) as u16 | (header[7] & 0xF0) as u16 | ((header[8] & 0x0F) as u16) << 8,
+                submapper: header[8] >> 4,
+                prg_rom_size: Self::rom_size("PRG ROM size", header[4], header[9] & 0x0F, 0x4000)?,
+                chr_rom_size: Self::rom_size("CHR ROM size", header[5], header[9] >> 4, 0x2000)?,
+                prg_ram_size: Self::ram_size("PRG RAM size", header[10] & 0x0F)?,
+                prg_nvram_size: Self::ram_size("PRG NVRAM size", header[10] >> 4)?,
+                chr_ram_size: Self::ram_size("CHR RAM size", header[11] & 0x0F)?,
+                chr_nvram_size: Self::ram_size("CHR NVRAM size", header[11] >> 4)?,
+                mirroring,
+                battery,
+                trainer,
+                region: match header[12] & 0x03 {
+                    0 => Region::Ntsc,
+                    1 => Region::Pal,
+                    2 => Region::Multi,
+                    _ => Region::Dendy
+                },
+                console_type,
+                misc_roms: header[14] & 0x03,
+                expansion_device: header[15] & 0x3F,
+            })
+        }
+
+
+        /*
+            ROM sizes are a 12 bit count of units, unless the high nibble is $F. Then the low byte is an
+            exponent-multiplier pair EEEEEEMM and the size is 2^E * (MM * 2 + 1) bytes
+        */
+        fn rom_size(field: &'static str, low: u8, high: u8, unit: usize) -> Result<usize, RomLoadError> {
+            if high != 0x0F {
+                return Ok(((high as usize) << 8 | low as usize) * unit);
+            }
+            let exponent = (low >> 2) as u32;
+            let multiplier = (low & 0x03) as usize * 2 + 1;
+            //Anything past 2^32 can't be a real ROM
+            if exponent > 32 {
+                return Err(RomLoadError::InvalidNes2Field { field, value: low });
+            }
+            Ok((1usize << exponent) * multiplier)
+        }
+
+
+        //RAM sizes are 64 << n bytes, 0 for none. Shift counts above 14 are reserved
+        fn ram_size(field: &'static str, shift: u8) -> Result<usize, RomLoadError> {
+            match shift {
+                0 => Ok(0),
+                1..=14 => Ok(64 << shift),
+                _ => Err(RomLoadError::InvalidNes2Field { field, value: shift })
+            }
+        }
+
+
+        //Work RAM and battery RAM share the $6000-$7FFF window
+        pub fn total_prg_ram_size(&self) -> usize {
+            self.prg_ram_size + self.prg_nvram_size
+        }
+
+
+        pub fn total_chr_ram_size(&self) -> usize {
+            self.chr_ram_size + self.chr_nvram_size
+        }
+    }
+
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        //A header with the magic in place and the given bytes set, everything else 0
+        fn header(bytes: &[(usize, u8)]) -> [u8; 16] {
+            let mut header = [0x4E, 0x45, 0x53, 0x1A, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+            for &(index, value) in bytes {
+                header[index] = value;
+            }
+            header
+        }
+
+        fn parse(bytes: &[(usize, u8)]) -> RomHeader {
+            RomHeader::parse(&header(bytes)).unwrap()
+        }
+
+        fn invalid_field(bytes: &[(usize, u8)]) -> (&'static str, u8) {
+            match RomHeader::parse(&header(bytes)) {
+                Err(RomLoadError::InvalidNes2Field { field, value }) => (field, value),
+                other => panic!("expected InvalidNes2Field, got {:?}", other)
+            }
+        }
+
+        #[test]
+        fn bad_magic() {
+            let mut bytes = header(&[]);
+            bytes[3] = 0x1B;
+            assert!(matches!(RomHeader::parse(&bytes), Err(RomLoadError::BadMagic)));
+        }
+
+        #[test]
+        fn nes2_is_detected_from_byte_7() {
+            assert!(!parse(&[]).nes2);
+            assert!(parse(&[(7, 0x08)]).nes2);
+            assert!(!parse(&[(7, 0x04)]).nes2);
+            assert!(!parse(&[(7, 0x0C)]).nes2);
+        }
+
+        #[test]
+        fn ines_header() {
+            let rom = parse(&[(4, 2), (5, 1), (6, 0x13), (7, 0x40), (9, 0x01)]);
+            assert_eq!(rom.mapper, 0x41);
+            assert_eq!(rom.prg_rom_size, 0x8000);
+            assert_eq!(rom.chr_rom_size, 0x2000);
+            assert_eq!(rom.mirroring, Mirroring::Vertical);
+            assert!(rom.battery);
+            assert_eq!(rom.prg_ram_size, 0);
+            assert_eq!(rom.prg_nvram_size, 0x2000);
+            assert_eq!(rom.chr_ram_size, 0);
+            assert_eq!(rom.region, Region::Pal);
+
+            let rom = parse(&[(4, 1), (6, 0x08), (8, 3)]);
+            assert_eq!(rom.mirroring, Mirroring::FourScreen);
+            assert_eq!(rom.prg_ram_size, 0x6000);
+            assert_eq!(rom.chr_ram_size, 0x2000);
+            assert_eq!(rom.region, Region::Ntsc);
+        }
+
+        #[test]
+        fn dirty_ines_header_ignores_bytes_7_to_15() {
+            //"DiskDude!" style junk from byte 7 on
+            let rom = parse(&[(6, 0x20), (7, 0x44), (8, 4), (9, 0x01), (12, b'D'), (13, b'u')]);
+            assert!(!rom.nes2);
+            assert_eq!(rom.mapper, 0x02);
+            assert_eq!(rom.prg_ram_size, 0x2000);
+            assert_eq!(rom.region, Region::Ntsc);
+            assert_eq!(rom.console_type, ConsoleType::Nes);
+
+            //Byte 7 alone is trusted when the tail is clear
+            assert_eq!(parse(&[(6, 0x20), (7, 0x40)]).mapper, 0x42);
+            assert_eq!(parse(&[(6, 0x20), (7, 0x40), (15, 1)]).mapper, 0x02);
+        }
+
+        #[test]
+        fn nes2_rom_sizes() {
+            let rom = parse(&[(4, 0x02), (5, 0x03), (7, 0x08), (9, 0x21)]);
+            assert_eq!(rom.prg_rom_size, 0x102 * 0x4000);
+            assert_eq!(rom.chr_rom_size, 0x203 * 0x2000);
+
+            //Exponent-multiplier: 2^10 * 3 and 2^12 * 7
+            let rom = parse(&[(4, 10 << 2 | 1), (5, 12 << 2 | 3), (7, 0x08), (9, 0xFF)]);
+            assert_eq!(rom.prg_rom_size, 3 * 1024);
+            assert_eq!(rom.chr_rom_size, 7 * 4096);
+        }
+
+        #[test]
+        fn nes2_ram_sizes() {
+            let rom = parse(&[(7, 0x08), (10, 0x97), (11, 0x07)]);
+            assert_eq!(rom.prg_ram_size, 0x2000);
+            assert_eq!(rom.prg_nvram_size, 0x8000);
+            assert_eq!(rom.total_prg_ram_size(), 0xA000);
+            assert_eq!(rom.chr_ram_size, 0x2000);
+            assert_eq!(rom.chr_nvram_size, 0);
+        }
+
+        #[test]
+        fn nes2_mapper_and_submapper() {
+            let rom = parse(&[(6, 0x40), (7, 0x18), (8, 0x35)]);
+            assert_eq!(rom.mapper, 0x514);
+            assert_eq!(rom.submapper, 3);
+        }
+
+        #[test]
+        fn nes2_region() {
+            assert_eq!(parse(&[(7, 0x08), (12, 0)]).region, Region::Ntsc);
+            assert_eq!(parse(&[(7, 0x08), (12, 1)]).region, Region::Pal);
+            assert_eq!(parse(&[(7, 0x08), (12, 2)]).region, Region::Multi);
+            assert_eq!(parse(&[(7, 0x08), (12, 3)]).region, Region::Dendy);
+        }
+
+        #[test]
+        fn nes2_console_type() {
+            assert_eq!(parse(&[(7, 0x08)]).console_type, ConsoleType::Nes);
+            assert_eq!(parse(&[(7, 0x09), (13, 0x21)]).console_type, ConsoleType::VsSystem { ppu: 1, hardware: 2 });
+            assert_eq!(parse(&[(7, 0x0A)]).console_type, ConsoleType::Playchoice);
+            assert_eq!(parse(&[(7, 0x0B), (13, 0x05)]).console_type, ConsoleType::Extended(5));
+            //iNES has no extended console type
+            assert_eq!(parse(&[(7, 0x03)]).console_type, ConsoleType::Nes);
+        }
+
+        #[test]
+        fn invalid_nes2_fields() {
+            assert_eq!(invalid_field(&[(4, 33 << 2), (7, 0x08), (9, 0x0F)]), ("PRG ROM size", 33 << 2));
+            assert_eq!(invalid_field(&[(5, 40 << 2), (7, 0x08), (9, 0xF0)]), ("CHR ROM size", 40 << 2));
+            assert_eq!(invalid_field(&[(7, 0x08), (10, 0x0F)]), ("PRG RAM size", 15));
+            assert_eq!(invalid_field(&[(7, 0x08), (10, 0xF0)]), ("PRG NVRAM size", 15));
+            assert_eq!(invalid_field(&[(7, 0x08), (11, 0x0F)]), ("CHR RAM size", 15));
+            assert_eq!(invalid_field(&[(7, 0x08), (11, 0xF0)]), ("CHR NVRAM size", 15));
+        }
+    }
+}
@@ -1,26 +1,63 @@
+#![allow(clippy::module_inception, clippy::upper_case_acronyms)]
+
 pub mod cpu;
 pub mod ppu;
+pub mod apu;
 pub mod cartridge;
+pub mod header;
 pub mod console;
+pub mod controller;
 pub mod nrom;
+pub mod mmc1;
+pub mod uxrom;
+pub mod cnrom;
+pub mod axrom;
+pub mod gxrom;
+pub mod bnrom;
+pub mod mmc3;
+pub mod mmc5;
+pub mod mmc2;
+pub mod vrc;
+pub mod vrc4;
+pub mod vrc6;
+pub mod vrc7;
+pub mod savestate;
+pub mod nestest;
 
+use std::cell::RefCell;
 use std::env;
 
 fn main() {
 
     let args: Vec<String> = env::args().collect();
 
+    //Test mode: JPlayVR2 --nestest [rom] [log], compares CPU execution against the nestest reference log
+    if args.len() > 1 && args[1] == "--nestest" {
+        let rom = args.get(2).cloned().unwrap_or(String::from("nestest.nes"));
+        let log = args.get(3).cloned().unwrap_or(String::from("nestest.log"));
+        match nestest::nestest::run(&rom, &log) {
+            Ok(lines) => {
+                println!("nestest passed, {} lines matched", lines);
+                std::process::exit(0);
+            },
+            Err(report) => {
+                eprintln!("{}", report);
+                std::process::exit(1);
+            }
+        }
+    }
+
     if args.len() != 2 {
-        println!("Single argument required");
-        std::process::exit(0);
+        eprintln!("Single argument required");
+        std::process::exit(2);
     } 
   
 
-    let cart = match cartridge::cartridge::Cartridge::load_rom(&args[1]) {
-        Ok(cart) => cart,
-        Err(msg) => {
-            println!("{}", msg);
-            std::process::exit(-1);
+    let cart = match cartridge::cartridge::Cartridge::load_rom_logged(&args[1], &mut |msg| println!("{}", msg)) {
+        Ok(cart) => RefCell::new(cart),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
         }
     };
 
@@ -29,6 +66,9 @@ fn main() {
         cpu: &mut cpu::cpu::Mos6502::new(&cart, &mut p),
     };
     
-    nes.start_console();
+    if let Err(err) = nes.start_console() {
+        eprintln!("Could not write save file: {}", err);
+        std::process::exit(1);
+    }
     
 }
\ No newline at end of file
@@ -0,0 +1,172 @@
+pub mod mmc1 {
+    use crate::cartridge::cartridge::{Mapper, Mapped, Mirroring};
+    use crate::savestate::savestate::stateful;
+
+    /*
+        MMC1 (SxROM), registers are loaded one bit at a time through a 5 bit serial shift register.
+        $8000-$9FFF Control, $A000-$BFFF CHR bank 0, $C000-$DFFF CHR bank 1, $E000-$FFFF PRG bank.
+        The bigger boards reuse the CHR bank registers for their extra address lines:
+        SNROM   8KB CHR RAM, CHR bit 4 disables PRG RAM
+        SOROM   16KB PRG RAM, CHR bit 3 selects the PRG RAM bank
+        SUROM   512KB PRG ROM, CHR bit 4 selects the 256KB PRG half
+        SXROM   512KB PRG ROM and 32KB PRG RAM, CHR bits 2-3 select the PRG RAM bank
+        SEROM   32KB PRG ROM wired straight to the CPU, the PRG bank register does nothing
+    */
+    pub struct Mmc1 {
+        prg_size: usize,
+        chr_size: usize,
+        prg_ram_size: usize,
+        chr_ram: bool,
+        prg_fixed: bool,
+
+        shift_register: u8, //Bit 4 set marks the register as empty, the 5th write shifts it out
+        control: u8,
+        chr_bank_0: u8,
+        chr_bank_1: u8,
+        prg_bank: u8,
+
+        //Writes on consecutive cycles are ignored, so only the first write of a RMW instruction counts
+        cycle: u64,
+        last_write_cycle: u64,
+    }
+
+    stateful!(Mmc1 { shift_register, control, chr_bank_0, chr_bank_1, prg_bank, cycle, last_write_cycle });
+
+    impl Mapper for Mmc1 {
+        fn cpu_read(&mut self, addr: u16) -> Mapped {
+            match addr {
+                0x6000..=0x7FFF => self.prg_ram_address(addr),
+                0x8000..=0xFFFF => Mapped::PrgRom(self.prg_address(addr)),
+                _ => Mapped::None
+            }
+        }
+
+        fn cpu_write(&mut self, addr: u16, value: u8) -> Mapped {
+            match addr {
+                0x6000..=0x7FFF => self.prg_ram_address(addr),
+                0x8000..=0xFFFF => {
+                    let consecutive = self.cycle == self.last_write_cycle + 1;
+                    self.last_write_cycle = self.cycle;
+                    if consecutive {
+                        return Mapped::None;
+                    }
+
+                    //Bit 7 resets the shift register and sets PRG mode 3
+                    if value & 0x80 != 0 {
+                        self.shift_register = 0x10;
+                        self.control |= 0x0C;
+                        return Mapped::None;
+                    }
+
+                    let full = self.shift_register & 0x01 != 0;
+                    self.shift_register = (self.shift_register >> 1) | (value & 0x01) << 4;
+                    if full {
+                        let data = self.shift_register;
+                        match addr {
+                            0x8000..=0x9FFF => self.control = data,
+                            0xA000..=0xBFFF => self.chr_bank_0 = data,
+                            0xC000..=0xDFFF => self.chr_bank_1 = data,
+                            _ => self.prg_bank = data
+                        }
+                        self.shift_register = 0x10;
+                    }
+                    Mapped::None
+                },
+                _ => Mapped::None
+            }
+        }
+
+        fn ppu_read(&mut self, addr: u16) -> Mapped {
+            Mapped::Chr(self.chr_address(addr))
+        }
+
+        fn ppu_write(&mut self, addr: u16, _value: u8) -> Mapped {
+            Mapped::Chr(self.chr_address(addr))
+        }
+
+        fn mirroring(&self) -> Mirroring {
+            match self.control & 0x03 {
+                0 => Mirroring::SingleScreenA,
+                1 => Mirroring::SingleScreenB,
+                2 => Mirroring::Vertical,
+                _ => Mirroring::Horizontal
+            }
+        }
+
+        fn cpu_clock(&mut self) {
+            self.cycle += 1;
+        }
+    }
+
+    impl Mmc1 {
+        pub fn new(prg_size: usize, chr_size: usize, chr_ram: bool, prg_ram_size: usize, prg_fixed: bool) -> Mmc1 {
+            Mmc1 {
+                prg_size,
+                chr_size: chr_size.max(0x2000),
+                prg_ram_size,
+                chr_ram,
+                prg_fixed,
+                shift_register: 0x10,
+                control: 0x0C,
+                chr_bank_0: 0,
+                chr_bank_1: 0,
+                prg_bank: 0,
+                cycle: 0,
+                last_write_cycle: 0,
+            }
+        }
+
+
+        fn prg_address(&self, addr: u16) -> usize {
+            if self.prg_fixed {
+                return (addr & 0x7FFF) as usize % self.prg_size.max(1);
+            }
+            let bank = (self.prg_bank & 0x0F) as usize;
+
+            //SUROM/SXROM, the switchable and the fixed banks both stay within the selected 256KB
+            let outer = if self.prg_size > 0x40000 {(self.chr_bank_0 as usize & 0x10) << 14} else {0};
+
+            let offset = match (self.control >> 2) & 0x03 {
+                //32KB mode, the low bit of the bank number is ignored
+                0 | 1 => (bank & 0x0E) * 0x4000 + (addr & 0x7FFF) as usize,
+                //First bank fixed at $8000, switchable bank at $C000
+                2 => match addr {
+                    0x8000..=0xBFFF => (addr & 0x3FFF) as usize,
+                    _ => bank * 0x4000 + (addr & 0x3FFF) as usize
+                },
+                //Switchable bank at $8000, last bank fixed at $C000
+                _ => match addr {
+                    0x8000..=0xBFFF => bank * 0x4000 + (addr & 0x3FFF) as usize,
+                    _ => 0x3C000 + (addr & 0x3FFF) as usize
+                }
+            };
+            (outer | (offset % 0x40000)) % self.prg_size.max(1)
+        }
+
+
+        fn chr_address(&self, addr: u16) -> usize {
+            let offset = if self.control & 0x10 == 0 {
+                //8KB mode, the low bit of the bank number is ignored
+                (self.chr_bank_0 as usize & 0x1E) * 0x1000 + (addr & 0x1FFF) as usize
+            } else {
+                let bank = if addr < 0x1000 {self.chr_bank_0} else {self.chr_bank_1};
+                bank as usize * 0x1000 + (addr & 0x0FFF) as usize
+            };
+            offset % self.chr_size
+        }
+
+
+        fn prg_ram_address(&self, addr: u16) -> Mapped {
+            //PRG bank bit 4 disables PRG RAM, on SNROM so does CHR bit 4
+            if self.prg_bank & 0x10 != 0 || (self.chr_ram && self.prg_size <= 0x40000 && self.chr_bank_0 & 0x10 != 0) {
+                return Mapped::None;
+            }
+            let bank = match self.prg_ram_size {
+                0x4000 => (self.chr_bank_0 as usize >> 3) & 0x01,
+                0x8000 => (self.chr_bank_0 as usize >> 2) & 0x03,
+                _ => 0
+            };
+            Mapped::PrgRam(bank * 0x2000 + (addr & 0x1FFF) as usize)
+        }
+    }
+}
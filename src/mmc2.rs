@@ -0,0 +1,109 @@
+pub mod mmc2 {
+    use crate::cartridge::cartridge::{Mapper, Mapped, Mirroring};
+    use crate::savestate::savestate::stateful;
+
+    /*
+        MMC2 (PxROM, mapper 9) and MMC4 (FxROM, mapper 10). Each 4KB pattern table has two CHR banks, a latch
+        picks between them and flips when the PPU fetches tile $FD or $FE from that table. The fetch that
+        sets the latch still uses the old bank.
+        $A000 PRG bank      $B000/$C000 CHR banks for $0000 (latch $FD/$FE)
+        $F000 Mirroring     $D000/$E000 CHR banks for $1000 (latch $FD/$FE)
+        MMC2 has an 8KB switchable PRG bank and the last three fixed, and only checks $0FD8/$0FE8 exactly
+        for the first latch. MMC4 has a 16KB switchable bank, the last one fixed, and PRG RAM.
+    */
+    pub struct Mmc2 {
+        prg_size: usize,
+        chr_size: usize,
+        mmc4: bool,
+
+        prg_bank: u8,
+        chr_banks: [[u8; 2]; 2], //[pattern table][latch]
+        latches: [usize; 2], //0 for $FD, 1 for $FE
+        horizontal: bool
+    }
+
+    stateful!(Mmc2 { prg_bank, chr_banks, latches, horizontal });
+
+    impl Mapper for Mmc2 {
+        fn cpu_read(&mut self, addr: u16) -> Mapped {
+            match addr {
+                0x6000..=0x7FFF if self.mmc4 => Mapped::PrgRam((addr & 0x1FFF) as usize),
+                0x8000..=0xFFFF => Mapped::PrgRom(self.prg_address(addr)),
+                _ => Mapped::None
+            }
+        }
+
+        fn cpu_write(&mut self, addr: u16, value: u8) -> Mapped {
+            match addr {
+                0x6000..=0x7FFF if self.mmc4 => return Mapped::PrgRam((addr & 0x1FFF) as usize),
+                0xA000..=0xAFFF => self.prg_bank = value & 0x0F,
+                0xB000..=0xBFFF => self.chr_banks[0][0] = value & 0x1F,
+                0xC000..=0xCFFF => self.chr_banks[0][1] = value & 0x1F,
+                0xD000..=0xDFFF => self.chr_banks[1][0] = value & 0x1F,
+                0xE000..=0xEFFF => self.chr_banks[1][1] = value & 0x1F,
+                0xF000..=0xFFFF => self.horizontal = value & 0x01 != 0,
+                _ => ()
+            }
+            Mapped::None
+        }
+
+        fn ppu_read(&mut self, addr: u16) -> Mapped {
+            let mapped = Mapped::Chr(self.chr_address(addr));
+            match addr {
+                0x0FD8 => self.latches[0] = 0,
+                0x0FE8 => self.latches[0] = 1,
+                0x0FD9..=0x0FDF if self.mmc4 => self.latches[0] = 0,
+                0x0FE9..=0x0FEF if self.mmc4 => self.latches[0] = 1,
+                0x1FD8..=0x1FDF => self.latches[1] = 0,
+                0x1FE8..=0x1FEF => self.latches[1] = 1,
+                _ => ()
+            }
+            mapped
+        }
+
+        fn ppu_write(&mut self, addr: u16, _value: u8) -> Mapped {
+            Mapped::Chr(self.chr_address(addr))
+        }
+
+        fn mirroring(&self) -> Mirroring {
+            if self.horizontal {Mirroring::Horizontal} else {Mirroring::Vertical}
+        }
+    }
+
+    impl Mmc2 {
+        pub fn new(prg_size: usize, chr_size: usize, mmc4: bool) -> Mmc2 {
+            Mmc2 {
+                prg_size,
+                chr_size: chr_size.max(0x2000),
+                mmc4,
+                prg_bank: 0,
+                chr_banks: [[0; 2]; 2],
+                latches: [1, 1],
+                horizontal: false
+            }
+        }
+
+
+        fn prg_address(&self, addr: u16) -> usize {
+            let offset = if self.mmc4 {
+                match addr {
+                    0x8000..=0xBFFF => self.prg_bank as usize * 0x4000 + (addr & 0x3FFF) as usize,
+                    _ => self.prg_size.saturating_sub(0x4000) + (addr & 0x3FFF) as usize
+                }
+            } else {
+                match addr {
+                    0x8000..=0x9FFF => self.prg_bank as usize * 0x2000 + (addr & 0x1FFF) as usize,
+                    _ => self.prg_size.saturating_sub(0x8000) + (addr - 0x8000) as usize
+                }
+            };
+            offset % self.prg_size.max(1)
+        }
+
+
+        fn chr_address(&self, addr: u16) -> usize {
+            let table = (addr >> 12) as usize & 0x01;
+            let bank = self.chr_banks[table][self.latches[table]] as usize;
+            (bank * 0x1000 + (addr & 0x0FFF) as usize) % self.chr_size
+        }
+    }
+}
@@ -0,0 +1,177 @@
+pub mod mmc3 {
+    use crate::cartridge::cartridge::{Mapper, Mapped, Mirroring};
+    use crate::savestate::savestate::stateful;
+
+    //A12 has to stay low for this many CPU cycles before a rising edge clocks the IRQ counter
+    const A12_FILTER_CYCLES: u64 = 3;
+
+    /*
+        MMC3 (TxROM), registers are in even/odd pairs across $8000-$FFFF:
+        $8000 Bank select       $8001 Bank data
+        $A000 Mirroring         $A001 PRG RAM protect
+        $C000 IRQ latch         $C001 IRQ reload
+        $E000 IRQ disable       $E001 IRQ enable
+        Bank registers R0-R1 are 2KB CHR banks, R2-R5 1KB CHR banks and R6-R7 8KB PRG banks.
+    */
+    pub struct Mmc3 {
+        prg_size: usize,
+        chr_size: usize,
+        four_screen: bool,
+
+        bank_select: u8,
+        banks: [u8; 8],
+        horizontal: bool,
+        prg_ram_protect: u8,
+
+        //Scanline counter, clocked by A12 rising edges which normally happen once per scanline at the sprite fetches
+        irq_latch: u8,
+        irq_counter: u8,
+        irq_reload: bool,
+        irq_enabled: bool,
+        irq: bool,
+        cycle: u64,
+        a12_low_since: Option<u64>,
+    }
+
+    stateful!(Mmc3 {
+        bank_select, banks, horizontal, prg_ram_protect, irq_latch, irq_counter, irq_reload, irq_enabled, irq, cycle,
+        a12_low_since
+    });
+
+    impl Mapper for Mmc3 {
+        fn cpu_read(&mut self, addr: u16) -> Mapped {
+            match addr {
+                0x6000..=0x7FFF if self.prg_ram_protect & 0x80 != 0 => Mapped::PrgRam((addr & 0x1FFF) as usize),
+                0x8000..=0xFFFF => Mapped::PrgRom(self.prg_address(addr)),
+                _ => Mapped::None
+            }
+        }
+
+        fn cpu_write(&mut self, addr: u16, value: u8) -> Mapped {
+            let even = addr & 0x01 == 0;
+            match addr {
+                //Bit 7 enables the RAM, bit 6 write protects it
+                0x6000..=0x7FFF if self.prg_ram_protect & 0xC0 == 0x80 => return Mapped::PrgRam((addr & 0x1FFF) as usize),
+                0x8000..=0x9FFF if even => self.bank_select = value,
+                0x8000..=0x9FFF => self.banks[(self.bank_select & 0x07) as usize] = value,
+                0xA000..=0xBFFF if even => self.horizontal = value & 0x01 != 0,
+                0xA000..=0xBFFF => self.prg_ram_protect = value,
+                0xC000..=0xDFFF if even => self.irq_latch = value,
+                0xC000..=0xDFFF => {
+                    self.irq_counter = 0;
+                    self.irq_reload = true;
+                },
+                0xE000..=0xFFFF if even => {
+                    self.irq_enabled = false;
+                    self.irq = false;
+                },
+                0xE000..=0xFFFF => self.irq_enabled = true,
+                _ => ()
+            }
+            Mapped::None
+        }
+
+        fn ppu_read(&mut self, addr: u16) -> Mapped {
+            Mapped::Chr(self.chr_address(addr))
+        }
+
+        fn ppu_write(&mut self, addr: u16, _value: u8) -> Mapped {
+            Mapped::Chr(self.chr_address(addr))
+        }
+
+        fn mirroring(&self) -> Mirroring {
+            if self.four_screen {
+                Mirroring::FourScreen
+            } else if self.horizontal {
+                Mirroring::Horizontal
+            } else {
+                Mirroring::Vertical
+            }
+        }
+
+        fn irq(&self) -> bool {
+            self.irq
+        }
+
+        //Short low periods, like the nametable fetches between sprite pattern fetches, are filtered out
+        fn ppu_address(&mut self, addr: u16) {
+            if addr & 0x1000 != 0 {
+                if let Some(since) = self.a12_low_since {
+                    if self.cycle - since >= A12_FILTER_CYCLES {
+                        self.clock_irq_counter();
+                    }
+                }
+                self.a12_low_since = None;
+            } else if self.a12_low_since.is_none() {
+                self.a12_low_since = Some(self.cycle);
+            }
+        }
+
+        fn cpu_clock(&mut self) {
+            self.cycle += 1;
+        }
+    }
+
+    impl Mmc3 {
+        pub fn new(prg_size: usize, chr_size: usize, four_screen: bool) -> Mmc3 {
+            Mmc3 {
+                prg_size,
+                //No CHR ROM means the board has 8KB of CHR RAM
+                chr_size: chr_size.max(0x2000),
+                four_screen,
+                bank_select: 0,
+                banks: [0, 2, 4, 5, 6, 7, 0, 1],
+                horizontal: false,
+                prg_ram_protect: 0x80,
+                irq_latch: 0,
+                irq_counter: 0,
+                irq_reload: false,
+                irq_enabled: false,
+                irq: false,
+                cycle: 0,
+                a12_low_since: None,
+            }
+        }
+
+
+        fn clock_irq_counter(&mut self) {
+            if self.irq_counter == 0 || self.irq_reload {
+                self.irq_counter = self.irq_latch;
+                self.irq_reload = false;
+            } else {
+                self.irq_counter -= 1;
+            }
+            if self.irq_counter == 0 && self.irq_enabled {
+                self.irq = true;
+            }
+        }
+
+
+        //Bank select bit 6 swaps $8000 and $C000, the other one is fixed to the second last bank
+        fn prg_address(&self, addr: u16) -> usize {
+            let last_bank = (self.prg_size / 0x2000).max(1) - 1;
+            let swapped = self.bank_select & 0x40 != 0;
+            let bank = match (addr >> 13) & 0x03 {
+                0 if swapped => last_bank - 1,
+                0 => self.banks[6] as usize,
+                1 => self.banks[7] as usize,
+                2 if swapped => self.banks[6] as usize,
+                2 => last_bank - 1,
+                _ => last_bank
+            };
+            (bank * 0x2000 + (addr & 0x1FFF) as usize) % self.prg_size.max(1)
+        }
+
+
+        //Bank select bit 7 swaps the 2KB banks at $0000-$0FFF with the 1KB banks at $1000-$1FFF
+        fn chr_address(&self, addr: u16) -> usize {
+            let addr = if self.bank_select & 0x80 != 0 {addr ^ 0x1000} else {addr};
+            let offset = match addr {
+                0x0000..=0x07FF => (self.banks[0] as usize & 0xFE) * 0x400 + (addr & 0x07FF) as usize,
+                0x0800..=0x0FFF => (self.banks[1] as usize & 0xFE) * 0x400 + (addr & 0x07FF) as usize,
+                _ => self.banks[2 + ((addr as usize - 0x1000) >> 10)] as usize * 0x400 + (addr & 0x03FF) as usize
+            };
+            offset % self.chr_size
+        }
+    }
+}
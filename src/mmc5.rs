@@ -0,0 +1,448 @@
+pub mod mmc5 {
+    use crate::cartridge::cartridge::{Mapper, Mapped, Mirroring};
+    use crate::apu::apu::Pulse;
+    use crate::savestate::savestate::stateful;
+
+    //The MMC5 considers the PPU out of frame once it hasn't read anything for this many CPU cycles
+    const IDLE_CYCLES: u8 = 3;
+
+    //Length counters and envelopes are clocked at a fixed 240Hz, there is no frame counter mode
+    const AUDIO_FRAME_CYCLES: u16 = 7457;
+
+    /*
+        Fetches counted from a detected scanline start, following the Ricoh2c02's fetch schedule. The third of the
+        three identical nametable reads is the one at dot 340 and counts as 0, tile 2's attribute and pattern bytes
+        at dots 3-7 are 1-3 and tiles 3-33 take 4 fetches each from dot 9 on. Dot 257 starts with one more
+        background nametable read, then the sprite fetch window (dots 257-320) has 4 fetches for each of the
+        8 sprites. What follows are the first two tiles of the next line.
+    */
+    const SPRITE_FETCHES_START: u16 = 4 + 31 * 4 + 1;
+    const SPRITE_FETCHES_END: u16 = SPRITE_FETCHES_START + 8 * 4 - 1;
+
+    /*
+        MMC5 (ExROM)
+        $5000-$5015 Audio: 2 pulse channels and 8-bit PCM
+        $5100 PRG mode              $5101 CHR mode
+        $5102/$5103 PRG RAM protect $5104 ExRAM mode
+        $5105 Nametable mapping     $5106/$5107 Fill tile and attribute
+        $5113-$5117 PRG banks       $5120-$512B CHR banks   $5130 CHR upper bits
+        $5200-$5202 Vertical split  $5203/$5204 Scanline IRQ
+        $5205/$5206 Multiplier      $5C00-$5FFF ExRAM
+        Sprites use the A set of CHR banks ($5120-$5127) and the background the B set ($5128-$512B), but only
+        with 8x16 sprites. With 8x8 sprites the set written last is used for everything.
+    */
+    pub struct Mmc5 {
+        prg_size: usize,
+        chr_size: usize,
+        prg_ram_size: usize,
+
+        prg_mode: u8,
+        chr_mode: u8,
+        prg_ram_protect: [u8; 2],
+        exram_mode: u8,
+        nametable_mapping: u8,
+        fill_tile: u8,
+        fill_attribute: u8,
+        prg_banks: [u8; 5],
+        chr_a: [u16; 8],
+        chr_b: [u16; 4],
+        chr_upper: u16,
+        last_written_b: bool,
+        exram: Vec<u8>,
+
+        split_control: u8,
+        split_scroll: u8,
+        split_bank: u8,
+        split_y: u8,
+
+        irq_compare: u8,
+        irq_enabled: bool,
+        irq_pending: bool,
+
+        multiplicand: u8,
+        multiplier: u8,
+
+        //Snooped from PPUCTRL
+        sprites_8x16: bool,
+
+        //Scanline detection and fetch tracking
+        in_frame: bool,
+        scanline: u8,
+        idle_cycles: u8,
+        last_address: u16,
+        address_matches: u8,
+        fetch_count: u16,
+        sprite_fetch: bool,
+        next_tile: u8,
+        tile_attribute: u8, //ExRAM byte of the current tile in extended attribute mode
+        split_tile: bool,
+
+        //Audio, PCM read mode (samples taken from CPU reads of $8000-$BFFF) isn't supported
+        pulse_1: Pulse,
+        pulse_2: Pulse,
+        pcm: u8,
+        pcm_control: u8,
+        audio_cycle: u16,
+        audio_even: bool,
+    }
+
+    stateful!(Mmc5 {
+        prg_mode, chr_mode, prg_ram_protect, exram_mode, nametable_mapping, fill_tile, fill_attribute, prg_banks,
+        chr_a, chr_b, chr_upper, last_written_b, exram, split_control, split_scroll, split_bank, split_y, irq_compare,
+        irq_enabled, irq_pending, multiplicand, multiplier, sprites_8x16, in_frame, scanline, idle_cycles,
+        last_address, address_matches, fetch_count, sprite_fetch, next_tile, tile_attribute, split_tile, pulse_1,
+        pulse_2, pcm, pcm_control, audio_cycle, audio_even
+    });
+
+    impl Mapper for Mmc5 {
+        fn cpu_read(&mut self, addr: u16) -> Mapped {
+            match addr {
+                0x5015 => Mapped::Value(self.pulse_1.active() as u8 | (self.pulse_2.active() as u8) << 1),
+                0x5204 => {
+                    let status = (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6;
+                    self.irq_pending = false;
+                    Mapped::Value(status)
+                },
+                0x5205 => Mapped::Value((self.multiplicand as u16 * self.multiplier as u16) as u8),
+                0x5206 => Mapped::Value(((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8),
+                //ExRAM is only readable by the CPU in modes 2 and 3
+                0x5C00..=0x5FFF if self.exram_mode >= 2 => Mapped::Value(self.exram[(addr & 0x03FF) as usize]),
+                0x6000..=0xFFFF => self.prg_mapping(addr),
+                _ => Mapped::None
+            }
+        }
+
+        fn cpu_write(&mut self, addr: u16, value: u8) -> Mapped {
+            match addr {
+                0x5000..=0x5003 => self.pulse_1.register_write(addr & 0x03, value),
+                0x5004..=0x5007 => self.pulse_2.register_write(addr & 0x03, value),
+                0x5010 => self.pcm_control = value,
+                //Writing 0 in PCM write mode is ignored
+                0x5011 if self.pcm_control & 0x01 == 0 && value != 0 => self.pcm = value,
+                0x5015 => {
+                    self.pulse_1.set_enabled(value & 0x01 != 0);
+                    self.pulse_2.set_enabled(value & 0x02 != 0);
+                },
+                0x5100 => self.prg_mode = value & 0x03,
+                0x5101 => self.chr_mode = value & 0x03,
+                0x5102 => self.prg_ram_protect[0] = value & 0x03,
+                0x5103 => self.prg_ram_protect[1] = value & 0x03,
+                0x5104 => self.exram_mode = value & 0x03,
+                0x5105 => self.nametable_mapping = value,
+                0x5106 => self.fill_tile = value,
+                0x5107 => self.fill_attribute = value & 0x03,
+                0x5113..=0x5117 => self.prg_banks[(addr - 0x5113) as usize] = value,
+                0x5120..=0x5127 => {
+                    self.chr_a[(addr - 0x5120) as usize] = value as u16 | self.chr_upper;
+                    self.last_written_b = false;
+                },
+                0x5128..=0x512B => {
+                    self.chr_b[(addr - 0x5128) as usize] = value as u16 | self.chr_upper;
+                    self.last_written_b = true;
+                },
+                0x5130 => self.chr_upper = (value as u16 & 0x03) << 8,
+                0x5200 => self.split_control = value,
+                0x5201 => {
+                    self.split_scroll = value;
+                    if !self.in_frame {
+                        self.split_y = value;
+                    }
+                },
+                0x5202 => self.split_bank = value,
+                0x5203 => self.irq_compare = value,
+                0x5204 => self.irq_enabled = value & 0x80 != 0,
+                0x5205 => self.multiplicand = value,
+                0x5206 => self.multiplier = value,
+                //In the nametable modes the CPU can only write while the PPU is rendering, otherwise 0 is written
+                0x5C00..=0x5FFF => match self.exram_mode {
+                    0 | 1 => self.exram[(addr & 0x03FF) as usize] = if self.in_frame {value} else {0},
+                    2 => self.exram[(addr & 0x03FF) as usize] = value,
+                    _ => ()
+                },
+                0x6000..=0xFFFF => {
+                    //RAM is writable only after $5102 = 2 and $5103 = 1
+                    if let Mapped::PrgRam(offset) = self.prg_mapping(addr) {
+                        if self.prg_ram_protect == [0x02, 0x01] {
+                            return Mapped::PrgRam(offset);
+                        }
+                    }
+                },
+                _ => ()
+            }
+            Mapped::None
+        }
+
+        fn ppu_read(&mut self, addr: u16) -> Mapped {
+            Mapped::Chr(self.chr_address(addr))
+        }
+
+        fn ppu_write(&mut self, addr: u16, _value: u8) -> Mapped {
+            Mapped::Chr(self.chr_address(addr))
+        }
+
+        //Quadrants mapped to ExRAM or fill mode are answered by nametable_read, their page here is unused
+        fn mirroring(&self) -> Mirroring {
+            let mut pages = [0; 4];
+            for (table, page) in pages.iter_mut().enumerate() {
+                *page = (self.nametable_mapping >> (table * 2)) & 0x01;
+            }
+            Mirroring::Custom(pages)
+        }
+
+        fn irq(&self) -> bool {
+            self.irq_pending && self.irq_enabled
+        }
+
+        fn ppu_address(&mut self, addr: u16) {
+            self.idle_cycles = 0;
+
+            //Three reads in a row from the same nametable address only happen at the end of a scanline
+            if addr == self.last_address {
+                self.address_matches += 1;
+            } else {
+                self.address_matches = 0;
+            }
+            self.last_address = addr;
+
+            self.fetch_count = self.fetch_count.saturating_add(1);
+            if self.address_matches == 2 && (0x2000..=0x2FFF).contains(&addr) {
+                self.scanline_detected();
+            }
+
+            self.sprite_fetch = self.in_frame && (SPRITE_FETCHES_START..=SPRITE_FETCHES_END).contains(&self.fetch_count);
+            if self.fetch_count == SPRITE_FETCHES_END + 1 {
+                //First fetch for the next scanline
+                self.next_tile = 0;
+                self.split_y = if self.split_y == 239 {0} else {self.split_y.wrapping_add(1)};
+            }
+
+            //Background tile fetch, work out whether the split covers this tile and grab its extended attribute
+            if self.in_frame && !self.sprite_fetch && (0x2000..=0x3EFF).contains(&addr) && addr & 0x03FF < 0x03C0 {
+                let tile_x = self.next_tile;
+                self.next_tile = self.next_tile.wrapping_add(1);
+
+                let threshold = self.split_control & 0x1F;
+                self.split_tile = self.split_control & 0x80 != 0 && self.exram_mode <= 1 &&
+                    if self.split_control & 0x40 != 0 {tile_x >= threshold} else {tile_x < threshold};
+                self.tile_attribute = self.exram[(addr & 0x03FF) as usize];
+            }
+        }
+
+        fn cpu_clock(&mut self) {
+            if self.idle_cycles < IDLE_CYCLES {
+                self.idle_cycles += 1;
+                if self.idle_cycles == IDLE_CYCLES {
+                    self.in_frame = false;
+                    self.irq_pending = false;
+                }
+            }
+
+            self.audio_even = !self.audio_even;
+            if self.audio_even {
+                self.pulse_1.clock_timer();
+                self.pulse_2.clock_timer();
+            }
+            self.audio_cycle += 1;
+            if self.audio_cycle == AUDIO_FRAME_CYCLES {
+                self.audio_cycle = 0;
+                self.pulse_1.quarter_frame();
+                self.pulse_2.quarter_frame();
+                self.pulse_1.half_frame();
+                self.pulse_2.half_frame();
+            }
+        }
+
+        fn nametable_read(&mut self, addr: u16) -> Option<u8> {
+            let offset = (addr & 0x03FF) as usize;
+            let attribute = offset >= 0x03C0;
+
+            if self.in_frame && !self.sprite_fetch {
+                if self.split_tile {
+                    return Some(self.split_nametable(attribute));
+                }
+                //Extended attributes, the palette in bits 6-7 is repeated so every quadrant selects it
+                if attribute && self.exram_mode == 1 {
+                    return Some((self.tile_attribute >> 6) * 0x55);
+                }
+            }
+
+            match (self.nametable_mapping >> (((addr >> 10) & 0x03) * 2)) & 0x03 {
+                2 => Some(if self.exram_mode <= 1 {self.exram[offset]} else {0}),
+                3 => Some(if attribute {self.fill_attribute * 0x55} else {self.fill_tile}),
+                _ => None
+            }
+        }
+
+        fn nametable_write(&mut self, addr: u16, value: u8) -> bool {
+            match (self.nametable_mapping >> (((addr >> 10) & 0x03) * 2)) & 0x03 {
+                2 => {
+                    if self.exram_mode <= 1 {
+                        self.exram[(addr & 0x03FF) as usize] = value;
+                    }
+                    true
+                },
+                3 => true,
+                _ => false
+            }
+        }
+
+        fn ppu_register_write(&mut self, addr: u16, value: u8) {
+            match addr & 0x2007 {
+                0x2000 => self.sprites_8x16 = value & 0x20 != 0,
+                0x2001 if value & 0x18 == 0 => self.in_frame = false,
+                _ => ()
+            }
+        }
+
+        //Pulses go through the same non-linear curve as the APU's, PCM at full scale is about as loud as the DMC
+        fn audio_output(&self) -> f32 {
+            let pulse = (self.pulse_1.output() + self.pulse_2.output()) as f32;
+            let pulse_out = if pulse == 0.0 {0.0} else {95.88 / (8128.0 / pulse + 100.0)};
+            pulse_out + self.pcm as f32 / 255.0 * 0.6
+        }
+    }
+
+    impl Mmc5 {
+        pub fn new(prg_size: usize, chr_size: usize, prg_ram_size: usize) -> Mmc5 {
+            Mmc5 {
+                prg_size,
+                chr_size: chr_size.max(0x2000),
+                prg_ram_size: prg_ram_size.max(0x2000),
+                prg_mode: 3,
+                chr_mode: 3,
+                prg_ram_protect: [0, 0],
+                exram_mode: 0,
+                nametable_mapping: 0,
+                fill_tile: 0,
+                fill_attribute: 0,
+                prg_banks: [0, 0xFF, 0xFF, 0xFF, 0xFF],
+                chr_a: [0; 8],
+                chr_b: [0; 4],
+                chr_upper: 0,
+                last_written_b: false,
+                exram: vec![0; 1024],
+                split_control: 0,
+                split_scroll: 0,
+                split_bank: 0,
+                split_y: 0,
+                irq_compare: 0,
+                irq_enabled: false,
+                irq_pending: false,
+                multiplicand: 0xFF,
+                multiplier: 0xFF,
+                sprites_8x16: false,
+                in_frame: false,
+                scanline: 0,
+                idle_cycles: 0,
+                last_address: 0,
+                address_matches: 0,
+                fetch_count: 0,
+                sprite_fetch: false,
+                next_tile: 0,
+                tile_attribute: 0,
+                split_tile: false,
+                pulse_1: Pulse::expansion(),
+                pulse_2: Pulse::expansion(),
+                pcm: 0,
+                pcm_control: 0,
+                audio_cycle: 0,
+                audio_even: false,
+            }
+        }
+
+
+        //The first detection after the PPU went idle is scanline 0, the IRQ fires when the count matches $5203
+        fn scanline_detected(&mut self) {
+            if self.in_frame {
+                self.scanline = self.scanline.wrapping_add(1);
+                if self.scanline == self.irq_compare {
+                    self.irq_pending = true;
+                }
+            } else {
+                self.in_frame = true;
+                self.scanline = 0;
+                self.split_y = self.split_scroll;
+            }
+            //The read that completed the detection is the nametable fetch of the third tile
+            self.fetch_count = 0;
+            self.next_tile = 2;
+        }
+
+
+        /*
+            $5113 picks the 8KB RAM bank at $6000. $5114-$5116 select ROM when bit 7 is set and RAM otherwise,
+            $5117 is always ROM. Bank numbers are in 8KB units, larger banks ignore the low bits.
+            Mode 0: 32KB ($5117)   Mode 1: 16KB ($5115) + 16KB ($5117)
+            Mode 2: 16KB ($5115) + 8KB ($5116) + 8KB ($5117)   Mode 3: 4 x 8KB ($5114-$5117)
+        */
+        fn prg_mapping(&self, addr: u16) -> Mapped {
+            let (register, size) = match (self.prg_mode, addr) {
+                (_, 0x6000..=0x7FFF) => (0, 0x2000),
+                (0, _) => (4, 0x8000),
+                (1, 0x8000..=0xBFFF) | (2, 0x8000..=0xBFFF) => (2, 0x4000),
+                (1, _) => (4, 0x4000),
+                (2, 0xC000..=0xDFFF) | (3, 0xC000..=0xDFFF) => (3, 0x2000),
+                (3, 0x8000..=0x9FFF) => (1, 0x2000),
+                (3, 0xA000..=0xBFFF) => (2, 0x2000),
+                _ => (4, 0x2000)
+            };
+            let value = self.prg_banks[register];
+            let low_bits = size / 0x2000 - 1;
+            let offset = (addr as usize) & (size - 1);
+
+            if register == 4 || (register != 0 && value & 0x80 != 0) {
+                let bank = (value & 0x7F) as usize & !low_bits;
+                Mapped::PrgRom((bank * 0x2000 + offset) % self.prg_size.max(1))
+            } else {
+                let bank = (value & 0x07) as usize & !low_bits;
+                Mapped::PrgRam((bank * 0x2000 + offset) % self.prg_ram_size)
+            }
+        }
+
+
+        fn chr_address(&self, addr: u16) -> usize {
+            let addr = addr as usize;
+
+            if self.in_frame && !self.sprite_fetch {
+                //Split region, 4KB page from $5202 with the split's own fine Y
+                if self.split_tile {
+                    let offset = self.split_bank as usize * 0x1000 + (addr & 0x0FF8) + (self.split_y & 0x07) as usize;
+                    return offset % self.chr_size;
+                }
+                //Extended attributes, bits 0-5 select a 4KB page for this tile
+                if self.exram_mode == 1 {
+                    let bank = (self.tile_attribute & 0x3F) as usize | (self.chr_upper as usize >> 2);
+                    return (bank * 0x1000 + (addr & 0x0FFF)) % self.chr_size;
+                }
+            }
+
+            let use_a = if self.sprites_8x16 && self.in_frame {self.sprite_fetch} else {!self.last_written_b};
+            //1 bank of 8KB, 2 of 4KB, 4 of 2KB or 8 of 1KB
+            let size = 0x2000 >> self.chr_mode;
+            let bank = if use_a {
+                let slot = addr / size;
+                self.chr_a[(slot + 1) * (8 >> self.chr_mode) - 1]
+            } else {
+                //The B set only covers 4KB, it repeats in both pattern tables
+                let slot = if self.chr_mode == 0 {0} else {(addr & 0x0FFF) / size};
+                let banks = ((1 << self.chr_mode) / 2).max(1);
+                self.chr_b[(slot + 1) * (4 / banks) - 1]
+            };
+            (bank as usize * size + (addr & (size - 1))) % self.chr_size
+        }
+
+
+        //Nametable and attribute bytes for the split region come from ExRAM, scrolled by the split's own Y
+        fn split_nametable(&self, attribute: bool) -> u8 {
+            let tile_x = (self.next_tile.wrapping_sub(1) & 0x1F) as usize;
+            let row = self.split_y as usize / 8;
+            if attribute {
+                let byte = self.exram[0x03C0 + (row / 4) * 8 + tile_x / 4];
+                let shift = ((row >> 1) & 0x01) * 4 + ((tile_x >> 1) & 0x01) * 2;
+                ((byte >> shift) & 0x03) * 0x55
+            } else {
+                self.exram[(row * 32 + tile_x) & 0x03FF]
+            }
+        }
+    }
+}
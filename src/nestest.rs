@@ -0,0 +1,139 @@
+pub mod nestest {
+    use std::cell::RefCell;
+    use std::fs::{self, File};
+    use std::path::Path;
+    use crate::cartridge::cartridge::Cartridge;
+    use crate::console::console::Console;
+    use crate::cpu::cpu::Mos6502;
+    use crate::ppu::ppu::Ricoh2c02;
+
+    //nestest runs its full automated test suite when started at $C000 instead of the reset vector
+    const AUTOMATION_START: u16 = 0xC000;
+
+    //The fields of a nestest.log line that are compared, the disassembly column is ignored
+    #[derive(PartialEq)]
+    struct TraceLine {
+        pc: u16,
+        bytes: Vec<u8>,
+        acc: u8,
+        ind_x: u8,
+        ind_y: u8,
+        stat: u8,
+        stck_pnt: u8,
+        scanline: u16,
+        dot: u16,
+        cycles: u64,
+    }
+
+
+    impl TraceLine {
+
+        /*
+            Parses a line in nestest.log format:
+            C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+        */
+        fn parse(line: &str) -> Option<TraceLine> {
+            let pc = u16::from_str_radix(line.get(0..4)?, 16).ok()?;
+            let bytes = line.get(6..14)?.split_whitespace()
+                .map(|b| u8::from_str_radix(b, 16).ok())
+                .collect::<Option<Vec<u8>>>()?;
+
+            let field = |name: &str| -> Option<&str> {
+                let start = line.find(name)? + name.len();
+                line[start..].split_whitespace().next()
+            };
+            let register = |name: &str| -> Option<u8> { u8::from_str_radix(field(name)?, 16).ok() };
+
+            //PPU field is 'PPU:sss,ddd' with both numbers right aligned to 3 characters
+            let ppu_start = line.find("PPU:")? + 4;
+            let mut ppu = line.get(ppu_start..ppu_start + 7)?.split(',');
+            let scanline = ppu.next()?.trim().parse().ok()?;
+            let dot = ppu.next()?.trim().parse().ok()?;
+
+            Some(TraceLine {
+                pc,
+                bytes,
+                acc: register("A:")?,
+                ind_x: register("X:")?,
+                ind_y: register("Y:")?,
+                stat: register("P:")?,
+                stck_pnt: register("SP:")?,
+                scanline,
+                dot,
+                cycles: field("CYC:")?.parse().ok()?,
+            })
+        }
+
+
+        //Names of every field that differs between the two lines
+        fn differences(&self, other: &TraceLine) -> Vec<&'static str> {
+            let mut fields = Vec::new();
+            if self.pc != other.pc { fields.push("PC"); }
+            if self.bytes != other.bytes { fields.push("opcode bytes"); }
+            if self.acc != other.acc { fields.push("A"); }
+            if self.ind_x != other.ind_x { fields.push("X"); }
+            if self.ind_y != other.ind_y { fields.push("Y"); }
+            if self.stat != other.stat { fields.push("P"); }
+            if self.stck_pnt != other.stck_pnt { fields.push("SP"); }
+            if self.scanline != other.scanline || self.dot != other.dot { fields.push("PPU"); }
+            if self.cycles != other.cycles { fields.push("CYC"); }
+            fields
+        }
+    }
+
+
+    //Boots nestest in automation mode and compares every executed instruction against the reference log.
+    //Returns the number of matching lines, or a report describing the first divergence.
+    pub fn run(rom_file: impl AsRef<Path>, log_file: impl AsRef<Path>) -> Result<usize, String> {
+        let log_file = log_file.as_ref();
+        let reference = fs::read_to_string(log_file).map_err(|_| format!("Could not open log file {}", log_file.display()))?;
+        let cart = RefCell::new(Cartridge::load_rom(rom_file).map_err(|err| err.to_string())?);
+
+        let mut p = Ricoh2c02::new(&cart);
+        let mut nes = Console {
+            cpu: &mut Mos6502::new(&cart, &mut p),
+        };
+
+        nes.cpu.set_log(File::create("CPU_LOG.txt").ok());
+        nes.cpu.reset(true);
+        nes.cpu.set_program_counter(AUTOMATION_START);
+
+        let mut previous = String::new();
+        let mut matched = 0;
+
+        for (line_number, expected_line) in reference.lines().enumerate() {
+            let expected = TraceLine::parse(expected_line)
+                .ok_or_else(|| format!("Could not parse line {} of {}", line_number + 1, log_file.display()))?;
+            let actual_line = nes.cpu.trace();
+            let actual = TraceLine::parse(&actual_line)
+                .ok_or_else(|| format!("Could not parse CPU trace: {}", actual_line))?;
+
+            if expected != actual {
+                return Err(format!(
+                    "Divergence at line {} ({} differs)\n  previous: {}\n  expected: {}\n  actual:   {}\n  test result codes: $02={:02X} $03={:02X}",
+                    line_number + 1, expected.differences(&actual).join(", "),
+                    previous, expected_line, actual_line,
+                    nes.cpu.peek(0x0002), nes.cpu.peek(0x0003)
+                ));
+            }
+
+            matched += 1;
+            previous = actual_line;
+            nes.step();
+        }
+
+        Ok(matched)
+    }
+
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn nestest_matches_reference_log() {
+            let result = run("nestest.nes", "nestest.log");
+            assert_eq!(result, Ok(8991));
+        }
+    }
+}
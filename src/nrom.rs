@@ -1,24 +1,45 @@
 pub mod nrom {
-    use crate::cartridge::cartridge::Mapper;
+    use crate::cartridge::cartridge::{Mapper, Mapped, Mirroring};
+    use crate::savestate::savestate::stateful;
 
     pub struct Nrom {
-        prg_bank_mirror: u16 //One 32KB bank or two 16KB banks where 0xC000-0xFFFF mirrors 0x8000-0xBFFF
+        prg_bank_mirror: u16, //One 32KB bank or two 16KB banks where 0xC000-0xFFFF mirrors 0x8000-0xBFFF
+        mirroring: Mirroring //Fixed by solder pads, from the header
     }
 
+    stateful!(Nrom {});
+
     impl Mapper for Nrom {
-        fn ppu_read(&self) -> usize {0}
-        fn cpu_read(&self, addr: u16) -> usize {
-            ((addr & self.prg_bank_mirror) - 0x8000) as usize
+        fn cpu_read(&mut self, addr: u16) -> Mapped {
+            match addr {
+                0x6000..=0x7FFF => Mapped::PrgRam((addr - 0x6000) as usize), //Family Basic
+                0x8000..=0xFFFF => Mapped::PrgRom(((addr & self.prg_bank_mirror) - 0x8000) as usize),
+                _ => Mapped::None
+            }
+        }
+        fn cpu_write(&mut self, addr: u16, _value: u8) -> Mapped {
+            match addr {
+                0x6000..=0x7FFF => Mapped::PrgRam((addr - 0x6000) as usize),
+                _ => Mapped::None
+            }
+        }
+        fn ppu_read(&mut self, addr: u16) -> Mapped {
+            Mapped::Chr(addr as usize)
+        }
+        fn ppu_write(&mut self, addr: u16, _value: u8) -> Mapped {
+            Mapped::Chr(addr as usize)
+        }
+        fn mirroring(&self) -> Mirroring {
+            self.mirroring
         }
-        fn cpu_write(&self, _addr: u16, _value: u8) {}
-        fn ppu_write(&self) {}
     }
 
     impl Nrom {
-        pub fn new(prg_mirrored: bool) -> Nrom {
+        pub fn new(prg_mirrored: bool, mirroring: Mirroring) -> Nrom {
             Nrom {
-                prg_bank_mirror: if prg_mirrored {0xBFFF} else {0xFFFF}
+                prg_bank_mirror: if prg_mirrored {0xBFFF} else {0xFFFF},
+                mirroring
             }
         }
     }
-}
\ No newline at end of file
+}
@@ -1,10 +1,14 @@
 pub mod ppu {
-    use crate::cartridge::cartridge::Cartridge;
+    use std::cell::RefCell;
+    use crate::cartridge::cartridge::{Cartridge, Mirroring};
+    use crate::header::header::Region;
+    use crate::savestate::savestate::stateful;
 
     pub struct Ricoh2c02<'a> {
-        pub cart: &'a Cartridge,
+        pub cart: &'a RefCell<Cartridge>,
 
-        vram: Vec<u8>,
+        vram: Vec<u8>, //2KB of CIRAM, plus the extra 2KB four-screen cartridges carry
+        palette_ram: [u8; 32],
         primary_oam: Vec<u8>,
         secondary_oam: Vec<u8>,
         ppudata_buffer: u8,
@@ -13,13 +17,17 @@ pub mod ppu {
         scanline_cycle: u16,
         is_odd_cycle: bool,
 
+        //Frame layout for the cartridge's region
+        vblank_line: u16,
+        pre_render_line: u16,
+        skip_odd_dot: bool,
+
         ppu_ctrl: u8,
         ppu_status: u8,
         ppu_mask: u8,
         oam_addr: u8,
         oam_data: u8,
         //ppu_scroll: u8,
-        ppu_addr: u8,
         //ppu_data: u8,
 
         vram_addr: u16, //v
@@ -27,37 +35,76 @@ pub mod ppu {
         fine_x_scroll: u8, //x
         write_toggle: bool, //w
 
-        vblank_flag_set: bool,
+        //Background pipeline, latches filled by the fetches and the shift registers they are loaded into every 8 cycles
+        next_tile_id: u8,
+        next_tile_attrib: u8,
+        next_tile_lsb: u8,
+        next_tile_msb: u8,
+        bg_pattern_low: u16,
+        bg_pattern_high: u16,
+        bg_attrib_low: u16,
+        bg_attrib_high: u16,
+
+        //Sprites found by evaluation for the next scanline and the data fetched for the ones on the current scanline
+        sprite_count: usize,
+        sprite_zero_next: bool,
+        sprite_zero_current: bool,
+        sprite_patterns_low: [u8; 8],
+        sprite_patterns_high: [u8; 8],
+        sprite_attribs: [u8; 8],
+        sprite_x: [u8; 8],
+
+        //256x240 NES colors, 6-bit values from palette RAM
+        frame: Vec<u8>,
+        frame_count: u64,
+
         nmi_occurred: bool,
         nmi_output: bool,
         supress_nmi: bool,
-        gen_nmi: bool,
     }
 
+    stateful!(Ricoh2c02<'_> {
+        vram, palette_ram, primary_oam, secondary_oam, ppudata_buffer, current_scanline, scanline_cycle, is_odd_cycle,
+        ppu_ctrl, ppu_status, ppu_mask, oam_addr, oam_data, vram_addr, temp_vram_addr, fine_x_scroll, write_toggle,
+        next_tile_id, next_tile_attrib, next_tile_lsb, next_tile_msb, bg_pattern_low, bg_pattern_high, bg_attrib_low,
+        bg_attrib_high, sprite_count, sprite_zero_next, sprite_zero_current, sprite_patterns_low, sprite_patterns_high,
+        sprite_attribs, sprite_x, frame_count, nmi_occurred, nmi_output, supress_nmi
+    });
+
 
 
     impl Ricoh2c02<'_> {
 
-        pub fn new(c: &Cartridge) -> Ricoh2c02 {
+        /*
+            NTSC    262 lines, vblank from 241, the pre-render line is one dot shorter on odd frames
+            PAL     312 lines, vblank from 241
+            Dendy   312 lines, 50 post-render lines so vblank starts at 291 like on NTSC
+        */
+        pub fn new(c: &RefCell<Cartridge>) -> Ricoh2c02<'_> {
+            let region = c.borrow().header.region;
             Ricoh2c02 { 
                 cart: c,
 
-                vram: vec![0; 2048],
+                vram: vec![0; 4096],
+                palette_ram: [0; 32],
                 primary_oam: vec![0; 256],
                 secondary_oam: vec![0; 64],
                 ppudata_buffer: 0,
 
-                current_scanline: 261,
+                current_scanline: 0,
                 scanline_cycle: 0,
                 is_odd_cycle: false,
 
+                vblank_line: if region == Region::Dendy {291} else {241},
+                pre_render_line: if region == Region::Pal || region == Region::Dendy {311} else {261},
+                skip_odd_dot: region == Region::Ntsc || region == Region::Multi,
+
                 ppu_ctrl: 0,
                 ppu_status: 0xA0,
                 ppu_mask: 0,
                 oam_addr: 0,
                 oam_data: 0,
                 //ppu_scroll: 0,
-                ppu_addr: 0,
                 //ppu_data: 0,
 
                 vram_addr: 0,
@@ -65,17 +112,35 @@ pub mod ppu {
                 fine_x_scroll: 0,
                 write_toggle: false,
 
+                next_tile_id: 0,
+                next_tile_attrib: 0,
+                next_tile_lsb: 0,
+                next_tile_msb: 0,
+                bg_pattern_low: 0,
+                bg_pattern_high: 0,
+                bg_attrib_low: 0,
+                bg_attrib_high: 0,
+
+                sprite_count: 0,
+                sprite_zero_next: false,
+                sprite_zero_current: false,
+                sprite_patterns_low: [0; 8],
+                sprite_patterns_high: [0; 8],
+                sprite_attribs: [0; 8],
+                sprite_x: [0; 8],
+
+                frame: vec![0; 256 * 240],
+                frame_count: 0,
+
             /*
                 Start of vertical blanking: Set NMI_occurred in PPU to true.
                 End of vertical blanking, sometime in pre-render scanline: Set NMI_occurred to false.
                 Read PPUSTATUS: Return old status of NMI_occurred in bit 7, then set NMI_occurred to false.
                 Write to PPUCTRL: Set NMI_output to bit 7. 
             */
-                vblank_flag_set: false,
                 nmi_occurred: false,
                 nmi_output: false,
                 supress_nmi: false,
-                gen_nmi: false,
              }
         }
 
@@ -88,12 +153,41 @@ pub mod ppu {
         }
 
 
+        pub fn scanline(&self) -> u16 {
+            self.current_scanline
+        }
+
+
+        pub fn dot(&self) -> u16 {
+            self.scanline_cycle
+        }
+
+
+        //Palette indices of the last completed frame, row major, 256 pixels per row
+        pub fn frame_buffer(&self) -> &[u8] {
+            &self.frame
+        }
+
+
+        //Incremented every time a frame is completed, at the start of vertical blanking
+        pub fn frame_count(&self) -> u64 {
+            self.frame_count
+        }
+
+
+        //State of the /NMI output, the CPU detects NMIs on its rising edge
+        pub fn nmi_line(&self) -> bool {
+            self.nmi_occurred && self.nmi_output
+        }
+
+
         pub fn register_read(&mut self, register_index: u8) -> u8 {
             match register_index {
                 0 | 1 | 3 | 5 | 6 => 0, //Should return open bus
                 2 => { //PPUSTATUS
                     let reg_value = if self.nmi_occurred {self.ppu_status | 0x80} else {self.ppu_status};
-                    self.supress_nmi = (self.current_scanline == 242) && (self.scanline_cycle < 3);
+                    //Reading one cycle before vblank starts suppresses the flag and the NMI for that frame
+                    self.supress_nmi = self.current_scanline == self.vblank_line && self.scanline_cycle == 0;
                     self.nmi_occurred = false;
                     self.ppu_status &= 0x7F;
                     self.write_toggle = false;
@@ -103,29 +197,41 @@ pub mod ppu {
                     //Pull value from OAM at address in OAMADDR
                     self.primary_oam[self.oam_addr as usize]
                 },
-                7 => { //PPUDATA when v is before palettes
+                7 => { //PPUDATA
                     //Read from vram from the address specified in PPUADDR, then increment PPUADDR
                     //Utilize the internal read buffer
-                    let buffer_val: u8 = self.ppudata_buffer;
-                    self.ppudata_buffer = self.vram[self.vram_addr as usize];
-                    self.vram_addr += if self.ppu_ctrl & 0x04 == 0 {1} else {32};
-                    buffer_val
+                    let addr = self.vram_addr & 0x3FFF;
+                    let value = if addr >= 0x3F00 {
+                        //Palette reads aren't buffered, the buffer gets the nametable byte "underneath" instead
+                        self.ppudata_buffer = self.ppu_read(addr - 0x1000);
+                        self.palette_read(addr)
+                    } else {
+                        let buffer_val: u8 = self.ppudata_buffer;
+                        self.ppudata_buffer = self.ppu_read(addr);
+                        buffer_val
+                    };
+                    self.increment_vram_addr();
+                    value
                 },
                 _ => 0
             }
         }
 
 
+        //OAM DMA writes go straight into OAM at OAMADDR, whatever line the PPU is on
+        pub fn oam_dma_write(&mut self, value: u8) {
+            self.oam_data = value;
+            self.primary_oam[self.oam_addr as usize] = value;
+            self.oam_addr = self.oam_addr.wrapping_add(1);
+        }
+
+
         //Writes to the PPUCTRL, PPUMASK, PPUADDR, PPUSCROLL are ignored if earlier than ~29658 CPU clocks after reset
-        pub fn register_write(&mut self, register_index: u8, value: u8, cycles_passed: u16) {
+        pub fn register_write(&mut self, register_index: u8, value: u8, cycles_passed: u64) {
             match register_index {
                 //If currently in vertical blank and PPUSTATUS has vblank flag is set, 
-                //changing bit 7 here from 0 to 1 generates an NMI
+                //changing bit 7 here from 0 to 1 generates an NMI, the CPU sees this as a rising edge on nmi_line
                 0 if cycles_passed > 29658 => {
-                    self.gen_nmi = self.ppu_ctrl & 0x80 == 0 && 
-                                        value & 0x80 != 0 &&
-                                        self.ppu_status & 0x80 != 0 &&
-                                        self.current_scanline > 240;
                     self.nmi_output = value & 0x80 > 0;
                     self.temp_vram_addr &= 0x73FF;
                     self.temp_vram_addr |= (value as u16 & 0x03) << 10;
@@ -139,12 +245,12 @@ pub mod ppu {
                 4 if self.current_scanline > 239 => {
                     self.oam_data = value;
                     self.primary_oam[self.oam_addr as usize] = value;
-                    self.oam_addr += 1;
+                    self.oam_addr = self.oam_addr.wrapping_add(1);
                 }
                 //PPUSCROLL - write toggle is false
                 5 if cycles_passed > 29658 && !self.write_toggle => {
                     self.temp_vram_addr &= 0xFFE0;
-                    self.temp_vram_addr |= value as u16 & 0x1F;
+                    self.temp_vram_addr |= value as u16 >> 3;
                     self.fine_x_scroll = value & 0x07;
                     self.write_toggle = true;
                 },
@@ -152,7 +258,7 @@ pub mod ppu {
                 5 if self.write_toggle => {
                     self.temp_vram_addr &= 0x0C1F;
                     self.temp_vram_addr |= (value as u16 & 0x07) << 12; 
-                    self.temp_vram_addr |= (value as u16 & 0xF1) << 2;
+                    self.temp_vram_addr |= (value as u16 & 0xF8) << 2;
                     self.write_toggle = false;
                 }
                 //PPUADDR - First write, toggle is false
@@ -167,105 +273,491 @@ pub mod ppu {
                     self.temp_vram_addr |= value as u16;
                     self.vram_addr = self.temp_vram_addr;
                     self.write_toggle = false;
+                    self.idle_bus_address();
                 },
                 //PPUDATA
                 7 => {
-                    self.vram[self.ppu_addr as usize] = value;
-                    self.vram_addr += if self.ppu_ctrl & 0x04 == 0 {1} else {32};
+                    self.ppu_write(self.vram_addr, value);
+                    self.increment_vram_addr();
                 }
                 _ => ()
             }
         }
 
+        //PPUDATA accesses move v by 1 or 32 depending on PPUCTRL bit 2
+        fn increment_vram_addr(&mut self) {
+            self.vram_addr = (self.vram_addr + if self.ppu_ctrl & 0x04 == 0 {1} else {32}) & 0x7FFF;
+            self.idle_bus_address();
+        }
+
+
+        //Outside of rendering v sits on the address bus, games toggle A12 through PPUADDR to clock MMC3 style counters
+        fn idle_bus_address(&mut self) {
+            if !self.rendering_enabled() || (240..self.pre_render_line).contains(&self.current_scanline) {
+                self.cart.borrow_mut().ppu_address(self.vram_addr & 0x3FFF);
+            }
+        }
+
+
+        /*
+            PPU address space:
+            $0000-$1FFF Pattern tables, on the cartridge
+            $2000-$2FFF Nametables, $3000-$3EFF mirrors them
+            $3F00-$3FFF Palette RAM
+        */
+        fn ppu_read(&mut self, addr: u16) -> u8 {
+            let addr = addr & 0x3FFF;
+            self.cart.borrow_mut().ppu_address(addr);
+            match addr {
+                0x0000..=0x1FFF => self.cart.borrow_mut().ppu_read(addr),
+                0x2000..=0x3EFF => {
+                    let mapped = self.cart.borrow_mut().nametable_read(addr);
+                    mapped.unwrap_or_else(|| self.vram[self.nametable_offset(addr)])
+                },
+                _ => self.palette_read(addr)
+            }
+        }
+
+
+        fn ppu_write(&mut self, addr: u16, value: u8) {
+            let addr = addr & 0x3FFF;
+            self.cart.borrow_mut().ppu_address(addr);
+            match addr {
+                0x0000..=0x1FFF => self.cart.borrow_mut().ppu_write(addr, value),
+                0x2000..=0x3EFF => {
+                    if !self.cart.borrow_mut().nametable_write(addr, value) {
+                        let offset = self.nametable_offset(addr);
+                        self.vram[offset] = value;
+                    }
+                },
+                _ => self.palette_ram[Self::palette_index(addr)] = value & 0x3F
+            }
+        }
+
+
+        /*
+            32 bytes mirrored through $3F00-$3FFF. $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C,
+            the sprite palettes share their transparent entry with the background ones.
+        */
+        fn palette_index(addr: u16) -> usize {
+            let index = (addr & 0x1F) as usize;
+            if index & 0x13 == 0x10 {index & 0x0F} else {index}
+        }
+
+
+        //Grayscale (PPUMASK bit 0) keeps only the column of gray colors
+        fn palette_read(&self, addr: u16) -> u8 {
+            let color = self.palette_ram[Self::palette_index(addr)];
+            if self.ppu_mask & 0x01 != 0 {color & 0x30} else {color}
+        }
+
+
+        /*
+            Maps one of the 4 logical nametables at $2000/$2400/$2800/$2C00 onto a 1KB page of vram.
+            The mirroring is asked from the cartridge on every access since mappers can switch it at any time.
+            Horizontal: $2000=$2400, $2800=$2C00    Vertical: $2000=$2800, $2400=$2C00
+        */
+        fn nametable_offset(&self, addr: u16) -> usize {
+            let table = (addr >> 10) & 0x03;
+            let page = match self.cart.borrow().mirroring() {
+                Mirroring::Horizontal => table >> 1,
+                Mirroring::Vertical => table & 0x01,
+                Mirroring::SingleScreenA => 0,
+                Mirroring::SingleScreenB => 1,
+                Mirroring::FourScreen => table,
+                Mirroring::Custom(pages) => pages[table as usize] as u16
+            };
+            (page as usize) << 10 | (addr & 0x03FF) as usize
+        }
+
+
+        fn rendering_enabled(&self) -> bool {
+            self.ppu_mask & 0x18 != 0
+        }
+
+
         //Based on the internal current cycle, perform one of several actions
+        //cycles_to_run is in PPU cycles, 3 for every CPU cycle
         pub fn generate_signal(&mut self, cycles_to_run: u16) {
-            //PPU generates 262 scanlines per frame
+            //PPU generates 262 scanlines per frame, 312 on PAL and Dendy
             //Each scanline takes 341 PPU cycles, one pixel produced per cycle
-            //Cycles 0-340 -- Pre-render scanline, this is one cycle shorter on odd frames
+            //Cycles 0-340 -- Pre-render scanline 261, this is one cycle shorter on odd frames
             //240 Visible scanlines 0-239
             //Post render scanline 240
-            //20 VBlank scanlines 241-261
+            //20 VBlank scanlines 241-260
             //At the start of vertical blanking, set nmi_occurred to true
             //After vertical blanking, sometime during pre-render, set nmi_occurred to false
-
-            match self.current_scanline {
-                261 => self.pre_render_scanline(),
-                0..=239 => self.visible_scanline(cycles_to_run),
-                240 => (),
-                241..=260 => self.vertical_blanking(),
-                _ => println!("Error: {} is not a valid scanline", self.current_scanline)
-            }
-
-
-            self.scanline_cycle += cycles_to_run;
-
-                       
-            if self.scanline_cycle > 340 {
-
-                //If going on to the pre-render line, clear the appropriate flags in PPUSTATUS
-                if self.current_scanline == 260 {
-                    self.ppu_status &= 0x1F;
+            for _ in 0..cycles_to_run {
+                match self.current_scanline {
+                    0..=239 => self.visible_scanline(),
+                    line if line == self.pre_render_line => self.pre_render_scanline(),
+                    line if line >= self.vblank_line => self.vertical_blanking(),
+                    _ => ()
                 }
 
-                //If going on to first vblank line, set flag in PPSTATUS and nim_occurred
-                if self.current_scanline == 240 {
-                    self.ppu_status |= 0x80;
-                    self.nmi_occurred = true;
+                self.scanline_cycle += 1;
+
+                //The last cycle of the pre-render scanline is skipped on odd frames when rendering
+                if self.skip_odd_dot && self.current_scanline == self.pre_render_line && self.scanline_cycle == 340 && self.is_odd_cycle && self.rendering_enabled() {
+                    self.scanline_cycle = 341;
                 }
 
-                self.current_scanline += 1;
-                if self.current_scanline > 261 {
-                    self.current_scanline = 0;
-                    if self.is_odd_cycle {
-                        self.scanline_cycle += 1;
+                if self.scanline_cycle > 340 {
+                    self.scanline_cycle = 0;
+                    self.current_scanline += 1;
+                    if self.current_scanline > self.pre_render_line {
+                        self.current_scanline = 0;
+                        self.is_odd_cycle = !self.is_odd_cycle;
                     }
                 }
-                self.scanline_cycle -= 341;
-            } 
-
+            }
         }
 
         
-        //Only for pre-filling the registers for the first two tiles of first visible scanline
-        //Although same memory accesses as regular scanline is still technically made
+        //Same memory accesses as a visible scanline but no pixels are output, it pre-fills the
+        //shift registers with the first two tiles of the first visible scanline
         fn pre_render_scanline(&mut self) {
+            //Leaving vertical blank, clear the vblank, sprite 0 and overflow flags in PPUSTATUS
+            if self.scanline_cycle == 1 {
+                self.ppu_status &= 0x1F;
+                self.nmi_occurred = false;
+            }
 
-        }
+            self.background_fetches();
+            //No evaluation happens here, so no sprites are ever drawn on scanline 0, but the fetches still take place
+            if self.scanline_cycle == 1 {
+                self.sprite_count = 0;
+                self.sprite_zero_next = false;
+            }
+            if (257..=320).contains(&self.scanline_cycle) {
+                self.sprite_fetches();
+            }
 
+            //Vertical scroll bits are reloaded from t over and over during these cycles
+            if self.rendering_enabled() && (280..=304).contains(&self.scanline_cycle) {
+                self.transfer_address_y();
+            }
+        }
 
 
-        fn visible_scanline(&mut self, cycles_to_run: u16) {
-            let tiles_loaded = (1..=cycles_to_run).fold(0,|acc, curr| 
-                if (self.scanline_cycle + curr as u16) % 9 == 0 {acc+1} else {acc});
 
+        fn visible_scanline(&mut self) {
             match self.scanline_cycle {
-            //Visible portion of scanline, cycles 1-256. 
-            //Cycle 0 is idle, shift registers are reloaded on cycle 257 but we ignore this
-                0..=256 => {
-                    //For tiles_loaded times, render a tile
+            //Visible portion of scanline, cycles 1-256. Cycle 0 is idle
+            //Secondary OAM is cleared during 1-64 and sprites for the next scanline are evaluated during 65-256
+                1..=256 => {
+                    self.background_fetches();
+                    self.render_pixel();
+                    if self.scanline_cycle == 64 {
+                        self.secondary_oam.fill(0xFF);
+                    }
+                    if self.scanline_cycle == 256 && self.rendering_enabled() {
+                        self.evaluate_sprites();
+                    }
                 },
             //Tile data for next scanline's sprites. 8 sprites, 4 fetches each, 2 cycles per fetch = 64 
-                257..=320 if self.scanline_cycle + cycles_to_run > 320 => {
-                    //If scanline_cycle + cycles_to_run exceeds 320, perform the fetches, ignore otherwise 
+                257..=320 => {
+                    self.background_fetches();
+                    self.sprite_fetches();
                 },
-            //First 2 tiles of next scanline fetched here
-                321..=336 if self.scanline_cycle + cycles_to_run > 336 => {
-                    //Same as cycles 257..320
-                },
-            //2 unused nametable bytes fetched, these plus first nametable fetch of next scanline are used
+            //First 2 tiles of next scanline fetched from 321-336
+            //2 unused nametable bytes fetched at 337-340, these plus first nametable fetch of next scanline are used
             //by MMC5 mapper to clock a scanline counter
-                337..=340 => {
-                    //If not imnplementing MMC5 then do nothing, we'll see...
+                _ => self.background_fetches()
+            }
+        }
+
+
+        fn sprite_height(&self) -> u16 {
+            if self.ppu_ctrl & 0x20 != 0 {16} else {8}
+        }
+
+
+        fn sprite_in_range(&self, y: u8) -> bool {
+            let row = self.current_scanline as i32 - y as i32;
+            row >= 0 && row < self.sprite_height() as i32
+        }
+
+
+        /*
+            Copies the first 8 sprites in range of the next scanline into secondary OAM.
+            Once 8 are found the hardware keeps scanning for the overflow flag, but increments the byte
+            index along with the sprite index, so it checks tile, attribute and X bytes as if they were Y.
+        */
+        fn evaluate_sprites(&mut self) {
+            self.sprite_count = 0;
+            self.sprite_zero_next = false;
+            let mut n = 0;
+
+            while n < 64 && self.sprite_count < 8 {
+                let y = self.primary_oam[n * 4];
+                self.secondary_oam[self.sprite_count * 4] = y;
+                if self.sprite_in_range(y) {
+                    self.secondary_oam[self.sprite_count * 4..self.sprite_count * 4 + 4].copy_from_slice(&self.primary_oam[n * 4..n * 4 + 4]);
+                    if n == 0 {
+                        self.sprite_zero_next = true;
+                    }
+                    self.sprite_count += 1;
+                }
+                n += 1;
+            }
+
+            let mut m = 0;
+            while n < 64 {
+                if self.sprite_in_range(self.primary_oam[n * 4 + m]) {
+                    self.ppu_status |= 0x20;
+                    break;
+                }
+                n += 1;
+                m = (m + 1) & 0x03;
+            }
+        }
+
+
+        //Each of the 8 sprite slots takes 8 cycles: two garbage nametable fetches then the two pattern bytes.
+        //Empty slots fetch tile $FF so the pattern table accesses still happen.
+        fn sprite_fetches(&mut self) {
+            if !self.rendering_enabled() { return; }
+            let cycle = self.scanline_cycle;
+            //OAMADDR is reset during sprite tile loading
+            self.oam_addr = 0;
+            if cycle == 257 {
+                self.sprite_zero_current = self.sprite_zero_next;
+            }
+
+            let slot = ((cycle - 257) / 8) as usize;
+            match (cycle - 257) % 8 {
+                0 | 2 => { self.ppu_read(0x2000 | (self.vram_addr & 0x0FFF)); },
+                4 | 6 => {
+                    let high_plane = (cycle - 257) % 8 == 6;
+                    let (y, tile, attrib, x) = if slot < self.sprite_count {
+                        (self.secondary_oam[slot * 4], self.secondary_oam[slot * 4 + 1], self.secondary_oam[slot * 4 + 2], self.secondary_oam[slot * 4 + 3])
+                    } else {
+                        (0xFF, 0xFF, 0xFF, 0xFF)
+                    };
+
+                    let mut row = (self.current_scanline.wrapping_sub(y as u16)) & (self.sprite_height() - 1);
+                    if attrib & 0x80 != 0 {
+                        row = self.sprite_height() - 1 - row; //Vertical flip
+                    }
+
+                    //8x16 sprites take the pattern table from bit 0 of the tile number, the bottom half is the next tile
+                    let addr = if self.sprite_height() == 16 {
+                        ((tile as u16 & 0x01) << 12) | ((tile as u16 & 0xFE) << 4) | ((row & 0x08) << 1) | (row & 0x07)
+                    } else {
+                        ((self.ppu_ctrl as u16 & 0x08) << 9) | ((tile as u16) << 4) | row
+                    };
+                    let mut pattern = self.ppu_read(addr + if high_plane {8} else {0});
+
+                    if slot >= self.sprite_count {
+                        pattern = 0;
+                    } else if attrib & 0x40 != 0 {
+                        pattern = pattern.reverse_bits(); //Horizontal flip
+                    }
+
+                    if high_plane {
+                        self.sprite_patterns_high[slot] = pattern;
+                    } else {
+                        self.sprite_patterns_low[slot] = pattern;
+                    }
+                    self.sprite_attribs[slot] = attrib;
+                    self.sprite_x[slot] = x;
                 },
                 _ => ()
             }
         }
 
 
+        //The first opaque sprite pixel at x, returns the pixel, the palette, whether it's behind the background and whether it belongs to sprite 0
+        fn sprite_pixel(&self, x: usize) -> Option<(u8, u8, bool, bool)> {
+            for i in 0..self.sprite_count {
+                let offset = x as i32 - self.sprite_x[i] as i32;
+                if !(0..8).contains(&offset) { continue; }
+
+                let bit = 0x80 >> offset;
+                let pixel = ((self.sprite_patterns_high[i] & bit != 0) as u8) << 1 | (self.sprite_patterns_low[i] & bit != 0) as u8;
+                if pixel != 0 {
+                    return Some((pixel, self.sprite_attribs[i] & 0x03, self.sprite_attribs[i] & 0x20 != 0, i == 0 && self.sprite_zero_current));
+                }
+            }
+            None
+        }
+
+
+        /*
+            Every 8 cycles the next tile's nametable byte, attribute byte and both pattern bytes are fetched, 2 cycles each.
+            The shift registers move one bit per cycle and are reloaded from the fetched latches when a tile is complete.
+        */
+        fn background_fetches(&mut self) {
+            if !self.rendering_enabled() { return; }
+            let cycle = self.scanline_cycle;
+
+            if (2..=257).contains(&cycle) || (321..=337).contains(&cycle) {
+                self.update_shifters();
+
+                match (cycle - 1) % 8 {
+                    0 => {
+                        self.load_shifters();
+                        self.next_tile_id = self.ppu_read(0x2000 | (self.vram_addr & 0x0FFF));
+                    },
+                    2 => {
+                        //Attribute table sits at the end of the nametable, one byte per 4x4 tile area
+                        let v = self.vram_addr;
+                        let attrib_addr = 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+                        let mut attrib = self.ppu_read(attrib_addr);
+                        //Each byte holds four 2x2 tile quadrants, select the one this tile is in
+                        if (v >> 5) & 0x02 != 0 { attrib >>= 4; }
+                        if v & 0x02 != 0 { attrib >>= 2; }
+                        self.next_tile_attrib = attrib & 0x03;
+                    },
+                    4 => {
+                        let addr = self.background_pattern_addr();
+                        self.next_tile_lsb = self.ppu_read(addr);
+                    },
+                    6 => {
+                        let addr = self.background_pattern_addr() + 8;
+                        self.next_tile_msb = self.ppu_read(addr);
+                    },
+                    7 => self.increment_scroll_x(),
+                    _ => ()
+                }
+            }
+
+            match cycle {
+                256 => self.increment_scroll_y(),
+                257 => {
+                    self.load_shifters();
+                    self.transfer_address_x();
+                },
+                //Unused nametable fetches
+                338 | 340 => self.next_tile_id = self.ppu_read(0x2000 | (self.vram_addr & 0x0FFF)),
+                _ => ()
+            }
+        }
+
+
+        //Low bitplane of the current row of the next tile, the high bitplane is 8 bytes further
+        fn background_pattern_addr(&self) -> u16 {
+            ((self.ppu_ctrl as u16 & 0x10) << 8) + ((self.next_tile_id as u16) << 4) + ((self.vram_addr >> 12) & 0x07)
+        }
+
+
+        fn update_shifters(&mut self) {
+            if self.ppu_mask & 0x08 != 0 {
+                self.bg_pattern_low <<= 1;
+                self.bg_pattern_high <<= 1;
+                self.bg_attrib_low <<= 1;
+                self.bg_attrib_high <<= 1;
+            }
+        }
+
+
+        //The next tile goes into the low byte, the attribute is expanded to 8 bits so it shifts in step with the pattern
+        fn load_shifters(&mut self) {
+            self.bg_pattern_low = (self.bg_pattern_low & 0xFF00) | self.next_tile_lsb as u16;
+            self.bg_pattern_high = (self.bg_pattern_high & 0xFF00) | self.next_tile_msb as u16;
+            self.bg_attrib_low = (self.bg_attrib_low & 0xFF00) | if self.next_tile_attrib & 0x01 != 0 {0xFF} else {0};
+            self.bg_attrib_high = (self.bg_attrib_high & 0xFF00) | if self.next_tile_attrib & 0x02 != 0 {0xFF} else {0};
+        }
+
+
+        /*
+            v layout: yyy NN YYYYY XXXXX
+            fine Y, nametable select, coarse Y, coarse X
+        */
+        fn increment_scroll_x(&mut self) {
+            if self.vram_addr & 0x001F == 31 {
+                //Wrap coarse X and switch horizontal nametable
+                self.vram_addr &= !0x001F;
+                self.vram_addr ^= 0x0400;
+            } else {
+                self.vram_addr += 1;
+            }
+        }
+
+
+        fn increment_scroll_y(&mut self) {
+            if self.vram_addr & 0x7000 != 0x7000 {
+                self.vram_addr += 0x1000;
+                return;
+            }
+
+            //Fine Y overflows into coarse Y, row 29 is the last row of a nametable
+            self.vram_addr &= !0x7000;
+            let mut coarse_y = (self.vram_addr & 0x03E0) >> 5;
+            if coarse_y == 29 {
+                coarse_y = 0;
+                self.vram_addr ^= 0x0800;
+            } else if coarse_y == 31 {
+                //Out of bounds coarse Y (attribute area) wraps without switching nametables
+                coarse_y = 0;
+            } else {
+                coarse_y += 1;
+            }
+            self.vram_addr = (self.vram_addr & !0x03E0) | (coarse_y << 5);
+        }
+
+
+        fn transfer_address_x(&mut self) {
+            self.vram_addr = (self.vram_addr & !0x041F) | (self.temp_vram_addr & 0x041F);
+        }
+
+
+        fn transfer_address_y(&mut self) {
+            self.vram_addr = (self.vram_addr & !0x7BE0) | (self.temp_vram_addr & 0x7BE0);
+        }
+
+
+        fn render_pixel(&mut self) {
+            let x = self.scanline_cycle as usize - 1;
+            let mut bg_pixel: u8 = 0;
+            let mut bg_palette: u8 = 0;
+
+            //Background enabled, PPUMASK bit 1 controls the leftmost 8 pixels
+            if self.ppu_mask & 0x08 != 0 && (self.ppu_mask & 0x02 != 0 || x >= 8) {
+                let bit: u16 = 0x8000 >> self.fine_x_scroll;
+                bg_pixel = ((self.bg_pattern_high & bit != 0) as u8) << 1 | (self.bg_pattern_low & bit != 0) as u8;
+                bg_palette = ((self.bg_attrib_high & bit != 0) as u8) << 1 | (self.bg_attrib_low & bit != 0) as u8;
+            }
+
+            //Sprites enabled, PPUMASK bit 2 controls the leftmost 8 pixels
+            let mut sprite = None;
+            if self.ppu_mask & 0x10 != 0 && (self.ppu_mask & 0x04 != 0 || x >= 8) {
+                sprite = self.sprite_pixel(x);
+            }
+
+            //Pixel value 0 is transparent and always uses the universal background color at $3F00
+            //Sprite palettes are the upper 16 entries of palette RAM
+            let palette_addr = match sprite {
+                Some((sprite_pixel, sprite_palette, behind, sprite_zero)) => {
+                    //Sprite 0 hit, both pixels opaque, never on the last pixel of the line
+                    if sprite_zero && bg_pixel != 0 && x != 255 {
+                        self.ppu_status |= 0x40;
+                    }
+                    if bg_pixel != 0 && behind {
+                        bg_palette << 2 | bg_pixel
+                    } else {
+                        0x10 | sprite_palette << 2 | sprite_pixel
+                    }
+                },
+                None => if bg_pixel == 0 {0} else {bg_palette << 2 | bg_pixel}
+            };
+            self.frame[self.current_scanline as usize * 256 + x] = self.palette_read(0x3F00 | palette_addr as u16);
+        }
+
+
         //20 scanlines worth of idling, aside from some flag setting
         fn vertical_blanking(&mut self) {
-
+            //If going on to first vblank line, set flag in PPSTATUS and nmi_occurred
+            if self.current_scanline == self.vblank_line && self.scanline_cycle == 1 {
+                if !self.supress_nmi {
+                    self.ppu_status |= 0x80;
+                    self.nmi_occurred = true;
+                }
+                self.supress_nmi = false;
+                self.frame_count += 1;
+            }
         }
     }
 
-}
\ No newline at end of file
+}
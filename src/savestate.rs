@@ -0,0 +1,240 @@
+pub mod savestate {
+    use std::error::Error;
+    use std::fmt;
+
+    /*
+        Save state layout, all values little endian:
+        0-3     "JPST"
+        4-5     Format version
+        6-13    ROM hash, FNV-1a of the PRG and CHR ROM
+        14-     CPU, PPU, APU, cartridge and controller state, in that order
+        Only what the machine can change is stored, anything derived from the ROM or its header is not.
+    */
+    const MAGIC: [u8; 4] = *b"JPST";
+    pub const VERSION: u16 = 1;
+
+    #[derive(Debug, PartialEq)]
+    pub enum StateError {
+        BadMagic,
+        UnsupportedVersion(u16),
+        RomMismatch { expected: u64, found: u64 },
+        Truncated,
+        Invalid(&'static str),
+    }
+
+    impl fmt::Display for StateError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                StateError::BadMagic => write!(f, "Not a save state"),
+                StateError::UnsupportedVersion(version) => write!(f, "Unsupported save state version {}", version),
+                StateError::RomMismatch { expected, found } => write!(f, "Save state is for another ROM, hash {:016X} instead of {:016X}", found, expected),
+                StateError::Truncated => write!(f, "Save state truncated"),
+                StateError::Invalid(what) => write!(f, "Invalid save state: {}", what)
+            }
+        }
+    }
+
+    impl Error for StateError {}
+
+
+    //FNV-1a, enough to tell ROMs apart without pulling in a hashing crate
+    pub fn rom_hash(data: &[&[u8]]) -> u64 {
+        let mut hash: u64 = 0xCBF29CE484222325;
+        for &byte in data.iter().flat_map(|part| part.iter()) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001B3);
+        }
+        hash
+    }
+
+
+    pub struct StateWriter {
+        data: Vec<u8>,
+    }
+
+    impl StateWriter {
+        pub fn new(rom_hash: u64) -> StateWriter {
+            let mut writer = StateWriter { data: Vec::new() };
+            writer.write_bytes(&MAGIC);
+            VERSION.save(&mut writer);
+            rom_hash.save(&mut writer);
+            writer
+        }
+
+        pub fn write_bytes(&mut self, bytes: &[u8]) {
+            self.data.extend_from_slice(bytes);
+        }
+
+        pub fn finish(self) -> Vec<u8> {
+            self.data
+        }
+    }
+
+
+    pub struct StateReader<'a> {
+        data: &'a [u8],
+    }
+
+    impl StateReader<'_> {
+        //Checks the header, the reader is left at the start of the machine state
+        pub fn new(data: &[u8], rom_hash: u64) -> Result<StateReader<'_>, StateError> {
+            let mut reader = StateReader { data };
+            if reader.read_bytes(4).map_err(|_| StateError::BadMagic)? != MAGIC {
+                return Err(StateError::BadMagic);
+            }
+            let mut version = 0u16;
+            version.load(&mut reader)?;
+            if version != VERSION {
+                return Err(StateError::UnsupportedVersion(version));
+            }
+            let mut found = 0u64;
+            found.load(&mut reader)?;
+            if found != rom_hash {
+                return Err(StateError::RomMismatch { expected: rom_hash, found });
+            }
+            Ok(reader)
+        }
+
+        pub fn read_bytes(&mut self, count: usize) -> Result<&[u8], StateError> {
+            if self.data.len() < count {
+                return Err(StateError::Truncated);
+            }
+            let (bytes, rest) = self.data.split_at(count);
+            self.data = rest;
+            Ok(bytes)
+        }
+
+        //Leftover bytes mean the state was written by a different layout
+        pub fn finish(self) -> Result<(), StateError> {
+            if self.data.is_empty() {Ok(())} else {Err(StateError::Invalid("trailing data"))}
+        }
+    }
+
+
+    //Anything that can be written to and restored from a save state
+    pub trait Stateful {
+        fn save(&self, state: &mut StateWriter);
+        fn load(&mut self, state: &mut StateReader) -> Result<(), StateError>;
+    }
+
+    macro_rules! stateful_int {
+        ($($type:ty),*) => {$(
+            impl Stateful for $type {
+                fn save(&self, state: &mut StateWriter) {
+                    state.write_bytes(&self.to_le_bytes());
+                }
+
+                fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+                    let bytes = state.read_bytes(std::mem::size_of::<$type>())?;
+                    *self = <$type>::from_le_bytes(bytes.try_into().unwrap());
+                    Ok(())
+                }
+            }
+        )*};
+    }
+
+    stateful_int!(u8, u16, u32, u64, i16, f32, f64);
+
+    //Stored as 64 bits so states don't depend on the host
+    impl Stateful for usize {
+        fn save(&self, state: &mut StateWriter) {
+            (*self as u64).save(state);
+        }
+
+        fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+            let mut value = 0u64;
+            value.load(state)?;
+            *self = usize::try_from(value).map_err(|_| StateError::Invalid("size out of range"))?;
+            Ok(())
+        }
+    }
+
+    impl Stateful for bool {
+        fn save(&self, state: &mut StateWriter) {
+            (*self as u8).save(state);
+        }
+
+        fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+            let mut value = 0u8;
+            value.load(state)?;
+            *self = value != 0;
+            Ok(())
+        }
+    }
+
+    impl<T: Stateful + Default> Stateful for Option<T> {
+        fn save(&self, state: &mut StateWriter) {
+            self.is_some().save(state);
+            if let Some(value) = self {
+                value.save(state);
+            }
+        }
+
+        fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+            let mut some = false;
+            some.load(state)?;
+            *self = if some {
+                let mut value = T::default();
+                value.load(state)?;
+                Some(value)
+            } else {
+                None
+            };
+            Ok(())
+        }
+    }
+
+    impl<T: Stateful, const N: usize> Stateful for [T; N] {
+        fn save(&self, state: &mut StateWriter) {
+            for value in self.iter() {
+                value.save(state);
+            }
+        }
+
+        fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+            for value in self.iter_mut() {
+                value.load(state)?;
+            }
+            Ok(())
+        }
+    }
+
+    //Memory sizes come from the ROM, a state with a different size belongs to some other cartridge or layout
+    impl Stateful for Vec<u8> {
+        fn save(&self, state: &mut StateWriter) {
+            self.len().save(state);
+            state.write_bytes(self);
+        }
+
+        fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+            let mut length = 0usize;
+            length.load(state)?;
+            if length != self.len() {
+                return Err(StateError::Invalid("memory size mismatch"));
+            }
+            self.copy_from_slice(state.read_bytes(length)?);
+            Ok(())
+        }
+    }
+
+
+    //Implements Stateful for a struct from the list of fields that make up its state
+    macro_rules! stateful {
+        ($type:ty { $($field:ident),* }) => {
+            impl $crate::savestate::savestate::Stateful for $type {
+                #[allow(unused_variables)]
+                fn save(&self, state: &mut $crate::savestate::savestate::StateWriter) {
+                    $( $crate::savestate::savestate::Stateful::save(&self.$field, state); )*
+                }
+
+                #[allow(unused_variables)]
+                fn load(&mut self, state: &mut $crate::savestate::savestate::StateReader) -> Result<(), $crate::savestate::savestate::StateError> {
+                    $( $crate::savestate::savestate::Stateful::load(&mut self.$field, state)?; )*
+                    Ok(())
+                }
+            }
+        };
+    }
+
+    pub(crate) use stateful;
+}
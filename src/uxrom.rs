@@ -0,0 +1,53 @@
+pub mod uxrom {
+    use crate::cartridge::cartridge::{Mapper, Mapped, Mirroring};
+    use crate::savestate::savestate::stateful;
+
+    //UxROM (mapper 2), switchable 16KB bank at $8000, last bank fixed at $C000, 8KB CHR RAM
+    pub struct Uxrom {
+        prg_size: usize,
+        prg_bank: u8,
+        mirroring: Mirroring,
+        bus_conflicts: bool
+    }
+
+    stateful!(Uxrom { prg_bank });
+
+    impl Mapper for Uxrom {
+        fn cpu_read(&mut self, addr: u16) -> Mapped {
+            match addr {
+                0x8000..=0xBFFF => Mapped::PrgRom((self.prg_bank as usize * 0x4000 + (addr & 0x3FFF) as usize) % self.prg_size.max(1)),
+                0xC000..=0xFFFF => Mapped::PrgRom(self.prg_size.saturating_sub(0x4000) + (addr & 0x3FFF) as usize),
+                _ => Mapped::None
+            }
+        }
+        fn cpu_write(&mut self, addr: u16, value: u8) -> Mapped {
+            if addr >= 0x8000 {
+                self.prg_bank = value;
+            }
+            Mapped::None
+        }
+        fn ppu_read(&mut self, addr: u16) -> Mapped {
+            Mapped::Chr(addr as usize)
+        }
+        fn ppu_write(&mut self, addr: u16, _value: u8) -> Mapped {
+            Mapped::Chr(addr as usize)
+        }
+        fn mirroring(&self) -> Mirroring {
+            self.mirroring
+        }
+        fn bus_conflicts(&self) -> bool {
+            self.bus_conflicts
+        }
+    }
+
+    impl Uxrom {
+        pub fn new(prg_size: usize, mirroring: Mirroring, bus_conflicts: bool) -> Uxrom {
+            Uxrom {
+                prg_size,
+                prg_bank: 0,
+                mirroring,
+                bus_conflicts
+            }
+        }
+    }
+}
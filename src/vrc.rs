@@ -0,0 +1,102 @@
+pub mod vrc {
+    use crate::savestate::savestate::stateful;
+
+    //CPU cycles are counted in thirds, 341 of them make a scanline (113.667 CPU cycles)
+    const PRESCALER_PERIOD: i16 = 341;
+
+    /*
+        IRQ counter shared by the VRC4, VRC6 and VRC7. It counts up from the latch and fires when it overflows
+        past $FF, either every CPU cycle or once per scanline using a prescaler that approximates the PPU.
+        Control: bit 0 re-enable after acknowledge, bit 1 enable, bit 2 cycle mode.
+    */
+    pub struct VrcIrq {
+        latch: u8,
+        counter: u8,
+        prescaler: i16,
+        enabled: bool,
+        enable_after_ack: bool,
+        cycle_mode: bool,
+        pending: bool,
+    }
+
+    stateful!(VrcIrq { latch, counter, prescaler, enabled, enable_after_ack, cycle_mode, pending });
+
+
+    impl Default for VrcIrq {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+
+    impl VrcIrq {
+        pub fn new() -> VrcIrq {
+            VrcIrq {
+                latch: 0,
+                counter: 0,
+                prescaler: PRESCALER_PERIOD,
+                enabled: false,
+                enable_after_ack: false,
+                cycle_mode: false,
+                pending: false,
+            }
+        }
+
+        pub fn write_latch_low(&mut self, value: u8) {
+            self.latch = (self.latch & 0xF0) | (value & 0x0F);
+        }
+
+        pub fn write_latch_high(&mut self, value: u8) {
+            self.latch = (self.latch & 0x0F) | (value & 0x0F) << 4;
+        }
+
+        pub fn write_latch(&mut self, value: u8) {
+            self.latch = value;
+        }
+
+        pub fn write_control(&mut self, value: u8) {
+            self.enable_after_ack = value & 0x01 != 0;
+            self.enabled = value & 0x02 != 0;
+            self.cycle_mode = value & 0x04 != 0;
+            self.pending = false;
+            if self.enabled {
+                self.counter = self.latch;
+                self.prescaler = PRESCALER_PERIOD;
+            }
+        }
+
+        pub fn pending(&self) -> bool {
+            self.pending
+        }
+
+        pub fn acknowledge(&mut self) {
+            self.pending = false;
+            self.enabled = self.enable_after_ack;
+        }
+
+        //Every CPU cycle
+        pub fn clock(&mut self) {
+            if !self.enabled {
+                return;
+            }
+            if self.cycle_mode {
+                self.clock_counter();
+            } else {
+                self.prescaler -= 3;
+                if self.prescaler <= 0 {
+                    self.prescaler += PRESCALER_PERIOD;
+                    self.clock_counter();
+                }
+            }
+        }
+
+        fn clock_counter(&mut self) {
+            if self.counter == 0xFF {
+                self.counter = self.latch;
+                self.pending = true;
+            } else {
+                self.counter += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+pub mod vrc4 {
+    use crate::cartridge::cartridge::{Mapper, Mapped, Mirroring};
+    use crate::vrc::vrc::VrcIrq;
+    use crate::savestate::savestate::stateful;
+
+    /*
+        Konami VRC2 and VRC4 (mappers 21, 22, 23, 25). The boards wire different CPU address lines to the chip's
+        two register select inputs, given here as masks so that submapper 0 can listen on both candidates:
+        21: VRC4a A1,A2  VRC4c A6,A7        22: VRC2a A1,A0
+        23: VRC4f A0,A1  VRC4e A2,A3  VRC2b A0,A1
+        25: VRC4b A1,A0  VRC4d A3,A2  VRC2c A1,A0
+        $8000 PRG bank at $8000 (or $C000)  $9000 Mirroring, $9002 PRG swap mode (VRC4)
+        $A000 PRG bank at $A000             $B000-$E003 CHR banks, low and high nibble pairs
+        $F000-$F003 IRQ latch low/high, control and acknowledge (VRC4)
+        The VRC2 has no IRQ or PRG RAM, just a one bit latch at $6000 games use for copy protection.
+    */
+    pub struct Vrc4 {
+        prg_size: usize,
+        chr_size: usize,
+        vrc2: bool,
+        chr_shift: bool, //VRC2a ignores the low bit of CHR bank numbers
+        select_lines: [u16; 2],
+
+        prg_banks: [u8; 2],
+        prg_swap: bool,
+        chr_banks: [u16; 8],
+        mirroring: u8,
+        microwire: u8,
+        irq: VrcIrq,
+    }
+
+    stateful!(Vrc4 { prg_banks, prg_swap, chr_banks, mirroring, microwire, irq });
+
+    impl Mapper for Vrc4 {
+        fn cpu_read(&mut self, addr: u16) -> Mapped {
+            match addr {
+                0x6000..=0x6FFF if self.vrc2 => Mapped::Value(((addr >> 8) as u8 & 0xFE) | self.microwire),
+                0x6000..=0x7FFF if !self.vrc2 => Mapped::PrgRam((addr & 0x1FFF) as usize),
+                0x8000..=0xFFFF => Mapped::PrgRom(self.prg_address(addr)),
+                _ => Mapped::None
+            }
+        }
+
+        fn cpu_write(&mut self, addr: u16, value: u8) -> Mapped {
+            let register = self.register(addr);
+            match addr {
+                0x6000..=0x6FFF if self.vrc2 => self.microwire = value & 0x01,
+                0x6000..=0x7FFF if !self.vrc2 => return Mapped::PrgRam((addr & 0x1FFF) as usize),
+                0x8000..=0x8FFF => self.prg_banks[0] = value & 0x1F,
+                0x9000..=0x9FFF if self.vrc2 => self.mirroring = value & 0x01,
+                0x9000..=0x9FFF if register < 2 => self.mirroring = value & 0x03,
+                0x9000..=0x9FFF => self.prg_swap = value & 0x02 != 0,
+                0xA000..=0xAFFF => self.prg_banks[1] = value & 0x1F,
+                0xB000..=0xEFFF => {
+                    let bank = (((addr - 0xB000) >> 12) * 2 + (register >> 1)) as usize;
+                    self.chr_banks[bank] = if register & 0x01 == 0 {
+                        (self.chr_banks[bank] & 0x1F0) | (value as u16 & 0x0F)
+                    } else {
+                        (self.chr_banks[bank] & 0x0F) | (value as u16 & 0x1F) << 4
+                    };
+                },
+                0xF000..=0xFFFF if !self.vrc2 => match register {
+                    0 => self.irq.write_latch_low(value),
+                    1 => self.irq.write_latch_high(value),
+                    2 => self.irq.write_control(value),
+                    _ => self.irq.acknowledge()
+                },
+                _ => ()
+            }
+            Mapped::None
+        }
+
+        fn ppu_read(&mut self, addr: u16) -> Mapped {
+            Mapped::Chr(self.chr_address(addr))
+        }
+
+        fn ppu_write(&mut self, addr: u16, _value: u8) -> Mapped {
+            Mapped::Chr(self.chr_address(addr))
+        }
+
+        fn mirroring(&self) -> Mirroring {
+            match self.mirroring {
+                0 => Mirroring::Vertical,
+                1 => Mirroring::Horizontal,
+                2 => Mirroring::SingleScreenA,
+                _ => Mirroring::SingleScreenB
+            }
+        }
+
+        fn irq(&self) -> bool {
+            self.irq.pending()
+        }
+
+        fn cpu_clock(&mut self) {
+            self.irq.clock();
+        }
+    }
+
+    impl Vrc4 {
+        pub fn new(mapper: u16, submapper: u8, prg_size: usize, chr_size: usize) -> Vrc4 {
+            let select_lines = match (mapper, submapper) {
+                (21, 1) => [0x02, 0x04],
+                (21, 2) => [0x40, 0x80],
+                (21, _) => [0x42, 0x84],
+                (22, _) => [0x02, 0x01],
+                (23, 1) | (23, 3) => [0x01, 0x02],
+                (23, 2) => [0x04, 0x08],
+                (23, _) => [0x05, 0x0A],
+                (25, 1) | (25, 3) => [0x02, 0x01],
+                (25, 2) => [0x08, 0x04],
+                _ => [0x0A, 0x05]
+            };
+            Vrc4 {
+                prg_size,
+                chr_size: chr_size.max(0x2000),
+                vrc2: mapper == 22 || submapper == 3,
+                chr_shift: mapper == 22,
+                select_lines,
+                prg_banks: [0, 0],
+                prg_swap: false,
+                chr_banks: [0; 8],
+                mirroring: 0,
+                microwire: 0,
+                irq: VrcIrq::new(),
+            }
+        }
+
+
+        //Register 0-3 within the $1000 block
+        fn register(&self, addr: u16) -> u16 {
+            (addr & self.select_lines[0] != 0) as u16 | ((addr & self.select_lines[1] != 0) as u16) << 1
+        }
+
+
+        //The swap mode exchanges $8000 and $C000, the other one is fixed to the second last bank
+        fn prg_address(&self, addr: u16) -> usize {
+            let last_bank = (self.prg_size / 0x2000).max(1) - 1;
+            let bank = match addr {
+                0x8000..=0x9FFF if self.prg_swap => last_bank.saturating_sub(1),
+                0x8000..=0x9FFF => self.prg_banks[0] as usize,
+                0xA000..=0xBFFF => self.prg_banks[1] as usize,
+                0xC000..=0xDFFF if self.prg_swap => self.prg_banks[0] as usize,
+                0xC000..=0xDFFF => last_bank.saturating_sub(1),
+                _ => last_bank
+            };
+            (bank * 0x2000 + (addr & 0x1FFF) as usize) % self.prg_size.max(1)
+        }
+
+
+        fn chr_address(&self, addr: u16) -> usize {
+            let mut bank = self.chr_banks[(addr >> 10) as usize & 0x07] as usize;
+            if self.chr_shift {
+                bank >>= 1;
+            }
+            (bank * 0x400 + (addr & 0x03FF) as usize) % self.chr_size
+        }
+    }
+}
@@ -0,0 +1,249 @@
+pub mod vrc6 {
+    use crate::cartridge::cartridge::{Mapper, Mapped, Mirroring};
+    use crate::vrc::vrc::VrcIrq;
+    use crate::savestate::savestate::stateful;
+
+    //Channel levels (pulses 0-15, sawtooth 0-31) to the APU mixer's scale, a full volume pulse matches an APU pulse
+    const OUTPUT_SCALE: f32 = 0.00996;
+
+    //Pulse channel, duty is 1-16 steps out of 16 and there is no length counter or envelope
+    #[derive(Default)]
+    struct Pulse {
+        ignore_duty: bool,
+        duty: u8,
+        volume: u8,
+        period: u16,
+        enabled: bool,
+        timer: u16,
+        step: u8,
+    }
+
+    stateful!(Pulse { ignore_duty, duty, volume, period, enabled, timer, step });
+
+    impl Pulse {
+        fn register_write(&mut self, register: u16, value: u8) {
+            match register {
+                0 => {
+                    self.ignore_duty = value & 0x80 != 0;
+                    self.duty = (value >> 4) & 0x07;
+                    self.volume = value & 0x0F;
+                },
+                1 => self.period = (self.period & 0x0F00) | value as u16,
+                _ => {
+                    self.period = (self.period & 0x00FF) | (value as u16 & 0x0F) << 8;
+                    self.enabled = value & 0x80 != 0;
+                    if !self.enabled {
+                        self.step = 0;
+                    }
+                }
+            }
+        }
+
+        fn clock(&mut self, shift: u8) {
+            if !self.enabled {
+                return;
+            }
+            if self.timer == 0 {
+                self.timer = self.period >> shift;
+                self.step = (self.step + 1) & 0x0F;
+            } else {
+                self.timer -= 1;
+            }
+        }
+
+        fn output(&self) -> u8 {
+            if self.enabled && (self.ignore_duty || self.step <= self.duty) {self.volume} else {0}
+        }
+    }
+
+
+    //Sawtooth, the accumulator gains the rate every other timer clock and resets after 7 additions
+    #[derive(Default)]
+    struct Sawtooth {
+        rate: u8,
+        period: u16,
+        enabled: bool,
+        timer: u16,
+        step: u8,
+        accumulator: u8,
+    }
+
+    stateful!(Sawtooth { rate, period, enabled, timer, step, accumulator });
+
+    impl Sawtooth {
+        fn register_write(&mut self, register: u16, value: u8) {
+            match register {
+                0 => self.rate = value & 0x3F,
+                1 => self.period = (self.period & 0x0F00) | value as u16,
+                _ => {
+                    self.period = (self.period & 0x00FF) | (value as u16 & 0x0F) << 8;
+                    self.enabled = value & 0x80 != 0;
+                    if !self.enabled {
+                        self.accumulator = 0;
+                        self.step = 0;
+                    }
+                }
+            }
+        }
+
+        fn clock(&mut self, shift: u8) {
+            if !self.enabled {
+                return;
+            }
+            if self.timer > 0 {
+                self.timer -= 1;
+                return;
+            }
+            self.timer = self.period >> shift;
+            self.step += 1;
+            if self.step == 14 {
+                self.step = 0;
+                self.accumulator = 0;
+            } else if self.step.is_multiple_of(2) {
+                self.accumulator = self.accumulator.wrapping_add(self.rate);
+            }
+        }
+
+        fn output(&self) -> u8 {
+            self.accumulator >> 3
+        }
+    }
+
+
+    /*
+        Konami VRC6 (mappers 24 and 26, 26 swaps the A0 and A1 lines)
+        $8000 16KB PRG bank at $8000        $C000 8KB PRG bank at $C000, the last bank is fixed at $E000
+        $9000-$9002 Pulse 1                 $9003 Audio halt and frequency scaling
+        $A000-$A002 Pulse 2                 $B000-$B002 Sawtooth
+        $B003 PPU banking mode, mirroring and PRG RAM enable
+        $D000-$E003 1KB CHR banks           $F000-$F002 IRQ latch, control and acknowledge
+        Only the 1KB CHR banking mode and the plain mirroring settings of $B003 are supported.
+    */
+    pub struct Vrc6 {
+        prg_size: usize,
+        chr_size: usize,
+        swap_lines: bool,
+
+        prg_16k: u8,
+        prg_8k: u8,
+        chr_banks: [u8; 8],
+        banking_mode: u8,
+        irq: VrcIrq,
+
+        halt: bool,
+        frequency_shift: u8,
+        pulse_1: Pulse,
+        pulse_2: Pulse,
+        sawtooth: Sawtooth,
+    }
+
+    stateful!(Vrc6 {
+        prg_16k, prg_8k, chr_banks, banking_mode, irq, halt, frequency_shift, pulse_1, pulse_2, sawtooth
+    });
+
+    impl Mapper for Vrc6 {
+        fn cpu_read(&mut self, addr: u16) -> Mapped {
+            match addr {
+                0x6000..=0x7FFF if self.banking_mode & 0x80 != 0 => Mapped::PrgRam((addr & 0x1FFF) as usize),
+                0x8000..=0xFFFF => Mapped::PrgRom(self.prg_address(addr)),
+                _ => Mapped::None
+            }
+        }
+
+        fn cpu_write(&mut self, addr: u16, value: u8) -> Mapped {
+            let register = if self.swap_lines {(addr & 0x01) << 1 | (addr & 0x02) >> 1} else {addr & 0x03};
+            match (addr & 0xF000, register) {
+                (0x6000..=0x7000, _) if self.banking_mode & 0x80 != 0 => return Mapped::PrgRam((addr & 0x1FFF) as usize),
+                (0x8000, _) => self.prg_16k = value & 0x0F,
+                (0x9000, 3) => {
+                    self.halt = value & 0x01 != 0;
+                    //Bit 2 takes priority over bit 1
+                    self.frequency_shift = if value & 0x04 != 0 {8} else if value & 0x02 != 0 {4} else {0};
+                },
+                (0x9000, _) => self.pulse_1.register_write(register, value),
+                (0xA000, 3) => (),
+                (0xA000, _) => self.pulse_2.register_write(register, value),
+                (0xB000, 3) => self.banking_mode = value,
+                (0xB000, _) => self.sawtooth.register_write(register, value),
+                (0xC000, _) => self.prg_8k = value & 0x1F,
+                (0xD000, _) => self.chr_banks[register as usize] = value,
+                (0xE000, _) => self.chr_banks[4 + register as usize] = value,
+                (0xF000, 0) => self.irq.write_latch(value),
+                (0xF000, 1) => self.irq.write_control(value),
+                (0xF000, 2) => self.irq.acknowledge(),
+                _ => ()
+            }
+            Mapped::None
+        }
+
+        fn ppu_read(&mut self, addr: u16) -> Mapped {
+            Mapped::Chr(self.chr_address(addr))
+        }
+
+        fn ppu_write(&mut self, addr: u16, _value: u8) -> Mapped {
+            Mapped::Chr(self.chr_address(addr))
+        }
+
+        fn mirroring(&self) -> Mirroring {
+            match (self.banking_mode >> 2) & 0x03 {
+                0 => Mirroring::Vertical,
+                1 => Mirroring::Horizontal,
+                2 => Mirroring::SingleScreenA,
+                _ => Mirroring::SingleScreenB
+            }
+        }
+
+        fn irq(&self) -> bool {
+            self.irq.pending()
+        }
+
+        fn cpu_clock(&mut self) {
+            self.irq.clock();
+            if !self.halt {
+                self.pulse_1.clock(self.frequency_shift);
+                self.pulse_2.clock(self.frequency_shift);
+                self.sawtooth.clock(self.frequency_shift);
+            }
+        }
+
+        fn audio_output(&self) -> f32 {
+            (self.pulse_1.output() + self.pulse_2.output() + self.sawtooth.output()) as f32 * OUTPUT_SCALE
+        }
+    }
+
+    impl Vrc6 {
+        pub fn new(prg_size: usize, chr_size: usize, swap_lines: bool) -> Vrc6 {
+            Vrc6 {
+                prg_size,
+                chr_size: chr_size.max(0x2000),
+                swap_lines,
+                prg_16k: 0,
+                prg_8k: 0,
+                chr_banks: [0; 8],
+                banking_mode: 0,
+                irq: VrcIrq::new(),
+                halt: false,
+                frequency_shift: 0,
+                pulse_1: Pulse::default(),
+                pulse_2: Pulse::default(),
+                sawtooth: Sawtooth::default(),
+            }
+        }
+
+
+        fn prg_address(&self, addr: u16) -> usize {
+            let offset = match addr {
+                0x8000..=0xBFFF => self.prg_16k as usize * 0x4000 + (addr & 0x3FFF) as usize,
+                0xC000..=0xDFFF => self.prg_8k as usize * 0x2000 + (addr & 0x1FFF) as usize,
+                _ => self.prg_size.saturating_sub(0x2000) + (addr & 0x1FFF) as usize
+            };
+            offset % self.prg_size.max(1)
+        }
+
+
+        fn chr_address(&self, addr: u16) -> usize {
+            let bank = self.chr_banks[(addr >> 10) as usize & 0x07] as usize;
+            (bank * 0x400 + (addr & 0x03FF) as usize) % self.chr_size
+        }
+    }
+}
@@ -0,0 +1,398 @@
+pub mod vrc7 {
+    use std::f32::consts::PI;
+    use crate::cartridge::cartridge::{Mapper, Mapped, Mirroring};
+    use crate::vrc::vrc::VrcIrq;
+    use crate::savestate::savestate::{stateful, StateError, StateReader, StateWriter, Stateful};
+
+    //The FM unit produces one sample every 36 CPU cycles, 49716Hz
+    const FM_DIVIDER: u8 = 36;
+    const FM_RATE: f32 = 49716.0;
+
+    //One full volume channel is about as loud as a full volume APU pulse
+    const OUTPUT_SCALE: f32 = 0.15;
+
+    //Envelope levels are in dB, the envelope generator bottoms out at 48dB
+    const MAX_ATTENUATION: f32 = 48.0;
+
+    /*
+        Built in instruments 1-15, instrument 0 is the custom one in registers $00-$07. Byte layout:
+        0/1 Modulator/carrier: tremolo, vibrato, sustained envelope, key scale rate, frequency multiplier
+        2   Modulator key scale level and total level
+        3   Carrier key scale level, carrier/modulator rectified sine, modulator feedback
+        4/5 Modulator/carrier attack and decay rates
+        6/7 Modulator/carrier sustain level and release rate
+    */
+    const PATCHES: [[u8; 8]; 15] = [
+        [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27], //Buzzy bell
+        [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12], //Guitar
+        [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12], //Wurly
+        [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27], //Flute
+        [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28], //Clarinet
+        [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4], //Synth
+        [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07], //Trumpet
+        [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17], //Organ
+        [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01], //Bells
+        [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02], //Vibes
+        [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12], //Vibraphone
+        [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16], //Tutti
+        [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02], //Fretless
+        [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6], //Synth bass
+        [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06]  //Sweep
+    ];
+
+    //Frequency multipliers, indexed by the low nibble of patch bytes 0/1
+    const MULTIPLIERS: [f32; 16] = [0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 10.0, 12.0, 12.0, 15.0, 15.0];
+
+    //Tremolo and vibrato LFOs
+    const TREMOLO_RATE: f32 = 3.7;
+    const TREMOLO_DEPTH: f32 = 4.8;
+    const VIBRATO_RATE: f32 = 6.4;
+    const VIBRATO_DEPTH: f32 = 0.004;
+
+
+    #[derive(Copy, Clone, PartialEq)]
+    enum EnvelopeState {
+        Attack,
+        Decay,
+        Sustain,
+        Release,
+    }
+
+    impl Stateful for EnvelopeState {
+        fn save(&self, state: &mut StateWriter) {
+            (*self as u8).save(state);
+        }
+
+        fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+            let mut value = 0u8;
+            value.load(state)?;
+            *self = match value {
+                0 => EnvelopeState::Attack,
+                1 => EnvelopeState::Decay,
+                2 => EnvelopeState::Sustain,
+                3 => EnvelopeState::Release,
+                _ => return Err(StateError::Invalid("envelope state"))
+            };
+            Ok(())
+        }
+    }
+
+
+    #[derive(Copy, Clone)]
+    struct Operator {
+        phase: f32, //In cycles, 0-1
+        state: EnvelopeState,
+        attenuation: f32,
+        output: f32,
+        previous_output: f32,
+    }
+
+    stateful!(Operator { phase, state, attenuation, output, previous_output });
+
+    impl Operator {
+        fn new() -> Operator {
+            Operator {
+                phase: 0.0,
+                state: EnvelopeState::Release,
+                attenuation: MAX_ATTENUATION,
+                output: 0.0,
+                previous_output: 0.0,
+            }
+        }
+
+        fn key_on(&mut self) {
+            self.phase = 0.0;
+            self.attenuation = MAX_ATTENUATION;
+            self.state = EnvelopeState::Attack;
+        }
+
+        /*
+            Rates follow the OPL timings, 4 * rate plus the key scale offset, every 4 steps doubles the speed.
+            A 96dB decay takes 39.28s at rate 4, a full attack 2.826s.
+        */
+        fn clock_envelope(&mut self, patch: &[u8; 8], index: usize, key_scale: u8, release_rate: u8) {
+            let rate_of = |rate: u8| -> f32 {
+                if rate == 0 {
+                    return 0.0;
+                }
+                let effective = (rate as u16 * 4 + key_scale as u16).min(63) as f32;
+                2f32.powf((effective - 4.0) / 4.0)
+            };
+            let attack_rate = patch[4 + index] >> 4;
+            let decay_rate = patch[4 + index] & 0x0F;
+            let sustain_level = (patch[6 + index] >> 4) as f32 * 3.0;
+            let sustained = patch[index] & 0x20 != 0;
+
+            match self.state {
+                EnvelopeState::Attack => {
+                    if attack_rate == 15 {
+                        self.attenuation = 0.0;
+                    } else {
+                        self.attenuation -= 96.0 / 2.826 * rate_of(attack_rate) / FM_RATE;
+                    }
+                    if self.attenuation <= 0.0 {
+                        self.attenuation = 0.0;
+                        self.state = EnvelopeState::Decay;
+                    }
+                },
+                EnvelopeState::Decay => {
+                    self.attenuation += 96.0 / 39.28 * rate_of(decay_rate) / FM_RATE;
+                    if self.attenuation >= sustain_level {
+                        self.attenuation = sustain_level;
+                        self.state = EnvelopeState::Sustain;
+                    }
+                },
+                //Percussive envelopes keep fading at the release rate while the key is held
+                EnvelopeState::Sustain => if !sustained {
+                    self.attenuation += 96.0 / 39.28 * rate_of(patch[6 + index] & 0x0F) / FM_RATE;
+                },
+                EnvelopeState::Release => self.attenuation += 96.0 / 39.28 * rate_of(release_rate) / FM_RATE
+            }
+            self.attenuation = self.attenuation.min(MAX_ATTENUATION);
+        }
+
+        //Phase modulation and the output are both in cycles / full scale
+        fn compute(&mut self, modulation: f32, attenuation: f32, rectified: bool) -> f32 {
+            let wave = (2.0 * PI * (self.phase + modulation)).sin();
+            let wave = if rectified && wave < 0.0 {0.0} else {wave};
+            self.previous_output = self.output;
+            self.output = wave * 10f32.powf(-(self.attenuation + attenuation) / 20.0);
+            self.output
+        }
+    }
+
+
+    #[derive(Copy, Clone)]
+    struct Channel {
+        fnum: u16,
+        block: u8,
+        key: bool,
+        sustain: bool,
+        instrument: u8,
+        volume: u8,
+        modulator: Operator,
+        carrier: Operator,
+    }
+
+    stateful!(Channel { fnum, block, key, sustain, instrument, volume, modulator, carrier });
+
+
+    /*
+        Yamaha OPLL derivative with 6 two-operator FM channels. The custom instrument is at registers $00-$07
+        $10-$15 F-number low bits   $20-$25 Sustain, key, block and F-number bit 8   $30-$35 Instrument and volume
+        Key scale level attenuation isn't modelled.
+    */
+    struct Fm {
+        address: u8,
+        custom: [u8; 8],
+        channels: [Channel; 6],
+        tremolo_phase: f32,
+        vibrato_phase: f32,
+        output: f32,
+    }
+
+    stateful!(Fm { address, custom, channels, tremolo_phase, vibrato_phase, output });
+
+    impl Fm {
+        fn new() -> Fm {
+            Fm {
+                address: 0,
+                custom: [0; 8],
+                channels: [Channel {
+                    fnum: 0,
+                    block: 0,
+                    key: false,
+                    sustain: false,
+                    instrument: 0,
+                    volume: 0,
+                    modulator: Operator::new(),
+                    carrier: Operator::new(),
+                }; 6],
+                tremolo_phase: 0.0,
+                vibrato_phase: 0.0,
+                output: 0.0,
+            }
+        }
+
+        fn write(&mut self, value: u8) {
+            let index = (self.address & 0x0F) as usize;
+            match self.address {
+                0x00..=0x07 => self.custom[index] = value,
+                0x10..=0x15 => self.channels[index].fnum = (self.channels[index].fnum & 0x100) | value as u16,
+                0x20..=0x25 => {
+                    let channel = &mut self.channels[index];
+                    channel.fnum = (channel.fnum & 0xFF) | (value as u16 & 0x01) << 8;
+                    channel.block = (value >> 1) & 0x07;
+                    channel.sustain = value & 0x20 != 0;
+                    let key = value & 0x10 != 0;
+                    if key && !channel.key {
+                        channel.modulator.key_on();
+                        channel.carrier.key_on();
+                    } else if !key && channel.key {
+                        channel.modulator.state = EnvelopeState::Release;
+                        channel.carrier.state = EnvelopeState::Release;
+                    }
+                    channel.key = key;
+                },
+                0x30..=0x35 => {
+                    self.channels[index].instrument = value >> 4;
+                    self.channels[index].volume = value & 0x0F;
+                },
+                _ => ()
+            }
+        }
+
+        fn step(&mut self) {
+            self.tremolo_phase = (self.tremolo_phase + TREMOLO_RATE / FM_RATE) % 1.0;
+            self.vibrato_phase = (self.vibrato_phase + VIBRATO_RATE / FM_RATE) % 1.0;
+            let tremolo = TREMOLO_DEPTH * (1.0 + (2.0 * PI * self.tremolo_phase).sin()) / 2.0;
+            let vibrato = 1.0 + VIBRATO_DEPTH * (2.0 * PI * self.vibrato_phase).sin();
+
+            let mut output = 0.0;
+            for channel in self.channels.iter_mut() {
+                let patch = if channel.instrument == 0 {self.custom} else {PATCHES[channel.instrument as usize - 1]};
+                //f = 49716 * F * 2^(block - 19) * multiplier
+                let base = channel.fnum as f32 * 2f32.powi(channel.block as i32 - 19);
+
+                //Released notes with the channel sustain bit set fade at rate 5
+                for (index, operator) in [&mut channel.modulator, &mut channel.carrier].into_iter().enumerate() {
+                    let key_scale = if patch[index] & 0x10 != 0 {channel.block << 1 | (channel.fnum >> 8) as u8} else {channel.block >> 1};
+                    let release_rate = if channel.sustain {5} else {patch[6 + index] & 0x0F};
+                    operator.clock_envelope(&patch, index, key_scale, release_rate);
+
+                    let vibrato = if patch[index] & 0x40 != 0 {vibrato} else {1.0};
+                    operator.phase = (operator.phase + base * MULTIPLIERS[(patch[index] & 0x0F) as usize] * vibrato) % 1.0;
+                }
+
+                let feedback = patch[3] & 0x07;
+                let modulator_feedback = if feedback == 0 {
+                    0.0
+                } else {
+                    (channel.modulator.output + channel.modulator.previous_output) * 2f32.powi(feedback as i32) / 256.0
+                };
+                let modulator_level = (patch[2] & 0x3F) as f32 * 0.75 + if patch[0] & 0x80 != 0 {tremolo} else {0.0};
+                let modulation = channel.modulator.compute(modulator_feedback, modulator_level, patch[3] & 0x08 != 0) * 2.0;
+
+                let carrier_level = channel.volume as f32 * 3.0 + if patch[1] & 0x80 != 0 {tremolo} else {0.0};
+                output += channel.carrier.compute(modulation, carrier_level, patch[3] & 0x10 != 0);
+            }
+            self.output = output * OUTPUT_SCALE;
+        }
+    }
+
+
+    /*
+        Konami VRC7 (mapper 85). VRC7a selects registers with A4, VRC7b with A3, both are decoded.
+        $8000/$8010 PRG banks at $8000/$A000    $9000 PRG bank at $C000, the last bank is fixed at $E000
+        $9010 FM register select                $9030 FM register data (VRC7a only)
+        $A000-$D010 1KB CHR banks               $E000 Mirroring, FM silence, PRG RAM enable
+        $E010 IRQ latch                         $F000/$F010 IRQ control and acknowledge
+    */
+    pub struct Vrc7 {
+        prg_size: usize,
+        chr_size: usize,
+
+        prg_banks: [u8; 3],
+        chr_banks: [u8; 8],
+        control: u8,
+        irq: VrcIrq,
+
+        fm: Fm,
+        fm_divider: u8,
+    }
+
+    stateful!(Vrc7 { prg_banks, chr_banks, control, irq, fm, fm_divider });
+
+    impl Mapper for Vrc7 {
+        fn cpu_read(&mut self, addr: u16) -> Mapped {
+            match addr {
+                0x6000..=0x7FFF if self.control & 0x80 != 0 => Mapped::PrgRam((addr & 0x1FFF) as usize),
+                0x8000..=0xFFFF => Mapped::PrgRom(self.prg_address(addr)),
+                _ => Mapped::None
+            }
+        }
+
+        fn cpu_write(&mut self, addr: u16, value: u8) -> Mapped {
+            let high = addr & 0x18 != 0;
+            match (addr & 0xF000, high) {
+                (0x6000..=0x7000, _) if self.control & 0x80 != 0 => return Mapped::PrgRam((addr & 0x1FFF) as usize),
+                (0x8000, false) => self.prg_banks[0] = value & 0x3F,
+                (0x8000, true) => self.prg_banks[1] = value & 0x3F,
+                (0x9000, false) => self.prg_banks[2] = value & 0x3F,
+                (0x9000, true) if addr & 0x20 != 0 => self.fm.write(value),
+                (0x9000, true) => self.fm.address = value,
+                (0xA000..=0xD000, _) => self.chr_banks[((addr - 0xA000) >> 11) as usize | high as usize] = value,
+                (0xE000, false) => self.control = value,
+                (0xE000, true) => self.irq.write_latch(value),
+                (0xF000, false) => self.irq.write_control(value),
+                (0xF000, true) => self.irq.acknowledge(),
+                _ => ()
+            }
+            Mapped::None
+        }
+
+        fn ppu_read(&mut self, addr: u16) -> Mapped {
+            Mapped::Chr(self.chr_address(addr))
+        }
+
+        fn ppu_write(&mut self, addr: u16, _value: u8) -> Mapped {
+            Mapped::Chr(self.chr_address(addr))
+        }
+
+        fn mirroring(&self) -> Mirroring {
+            match self.control & 0x03 {
+                0 => Mirroring::Vertical,
+                1 => Mirroring::Horizontal,
+                2 => Mirroring::SingleScreenA,
+                _ => Mirroring::SingleScreenB
+            }
+        }
+
+        fn irq(&self) -> bool {
+            self.irq.pending()
+        }
+
+        fn cpu_clock(&mut self) {
+            self.irq.clock();
+            self.fm_divider += 1;
+            if self.fm_divider == FM_DIVIDER {
+                self.fm_divider = 0;
+                self.fm.step();
+            }
+        }
+
+        fn audio_output(&self) -> f32 {
+            if self.control & 0x40 != 0 {0.0} else {self.fm.output}
+        }
+    }
+
+    impl Vrc7 {
+        pub fn new(prg_size: usize, chr_size: usize) -> Vrc7 {
+            Vrc7 {
+                prg_size,
+                chr_size: chr_size.max(0x2000),
+                prg_banks: [0; 3],
+                chr_banks: [0; 8],
+                control: 0,
+                irq: VrcIrq::new(),
+                fm: Fm::new(),
+                fm_divider: 0,
+            }
+        }
+
+
+        fn prg_address(&self, addr: u16) -> usize {
+            let bank = match addr {
+                0x8000..=0xDFFF => self.prg_banks[((addr - 0x8000) >> 13) as usize] as usize,
+                _ => (self.prg_size / 0x2000).max(1) - 1
+            };
+            (bank * 0x2000 + (addr & 0x1FFF) as usize) % self.prg_size.max(1)
+        }
+
+
+        fn chr_address(&self, addr: u16) -> usize {
+            let bank = self.chr_banks[(addr >> 10) as usize & 0x07] as usize;
+            (bank * 0x400 + (addr & 0x03FF) as usize) % self.chr_size
+        }
+    }
+}